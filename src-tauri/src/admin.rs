@@ -1,10 +1,11 @@
 #[cfg(windows)]
 pub fn reopen_as_admin() {
   use std::process::{exit, Command};
+  use tracing::info;
 
   let install = std::env::current_exe().unwrap();
 
-  println!("Opening as admin: {}", install.to_str().unwrap());
+  info!("Opening as admin: {}", install.to_str().unwrap());
 
   Command::new("powershell.exe")
     .arg("powershell")
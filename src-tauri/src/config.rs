@@ -1,10 +1,33 @@
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use crate::error::CultivationError;
+use crate::games;
+use crate::hooks::Hooks;
+use crate::logging;
+use crate::paths;
+use crate::profiles::ServerProfile;
+use crate::system_helpers;
+use notify::{RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::string::String;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use tauri::Manager;
+use tracing::{info, warn};
+
+// Bump this and add a step to MIGRATIONS whenever the stored format changes
+pub const CONFIG_VERSION: u64 = 1;
 
 // Config may not exist, or may be old, so it's okay if these are optional
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Configuration {
+  pub config_version: Option<u64>,
   pub toggle_grasscutter: Option<bool>,
   pub game_install_path: Option<String>,
   pub grasscutter_with_game: Option<bool>,
@@ -13,7 +36,8 @@ pub struct Configuration {
   pub close_action: Option<u64>,
   pub startup_launch: Option<bool>,
   pub last_ip: Option<String>,
-  pub last_port: Option<String>,
+  #[serde(default, deserialize_with = "deserialize_port")]
+  pub last_port: Option<u16>,
   pub language: Option<String>,
   pub custom_background: Option<String>,
   pub use_theme_background: Option<bool>,
@@ -28,22 +52,824 @@ pub struct Configuration {
   pub auto_mongodb: Option<bool>,
   pub un_elevated: Option<bool>,
   pub redirect_more: Option<bool>,
+  // Sent to the server on top of the game's own domains, usually set by a profile
+  pub redirect_domains: Option<Vec<String>>,
   pub launch_args: Option<String>,
   pub offline_mode: Option<bool>,
+  pub profiles: Option<BTreeMap<String, ServerProfile>>,
+  pub active_profile: Option<String>,
+  // Name -> executable of each registered game, for switching between them, see games::select_game
+  pub games: Option<BTreeMap<String, String>>,
+  // Commands run around launching the game, see hooks::run
+  pub hooks: Option<Hooks>,
+
+  // Keys only the frontend knows about (migoto_path, grasscutter_elevation, ...)
+  #[serde(flatten)]
+  pub extra: Map<String, Value>,
 }
 
-pub fn config_path() -> PathBuf {
-  let mut path = tauri::api::path::data_dir().unwrap();
-  path.push("cultivation");
-  path.push("configuration.json");
+// 'close_action': 0 = close, 1 = tray
+const MAX_CLOSE_ACTION: u64 = 1;
+
+// Step `i` upgrades a config from version `i` to `i + 1`
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[migrate_ports_to_integers];
+
+// eg. CULTIVATION_LAST_IP=localhost overrides last_ip
+const ENV_PREFIX: &str = "CULTIVATION_";
+
+// Parsed once and kept until configuration.json changes, so hot paths like the proxy don't re-read it
+static CONFIG_CACHE: Lazy<RwLock<Option<Arc<Configuration>>>> = Lazy::new(|| RwLock::new(None));
+// Set when the file or the overrides changed. The cached config stays, in case the file is now broken.
+static CACHE_STALE: AtomicBool = AtomicBool::new(false);
+
+// Editors (and write_config) touch the file several times per save
+const WATCH_SETTLE_TIME: Duration = Duration::from_millis(200);
+
+// The settings of the profile passed with --profile, see profiles::use_profile
+static SESSION_OVERRIDES: Lazy<Mutex<Map<String, Value>>> = Lazy::new(|| Mutex::new(Map::new()));
+
+// Values passed with --set key=value
+static CLI_OVERRIDES: Lazy<Mutex<Map<String, Value>>> = Lazy::new(|| Mutex::new(Map::new()));
 
-  path
+// Where an effective config value came from, in order of precedence (lowest first)
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+  Default,
+  File,
+  Profile,
+  Env,
+  Cli,
+}
+
+type ConfigSources = BTreeMap<String, ConfigSource>;
+
+#[derive(Serialize)]
+pub struct EffectiveConfig {
+  pub config: Configuration,
+  pub sources: ConfigSources,
+}
+
+// Older configs (and the frontend's text input) store the port as a string
+pub fn deserialize_port<'de, D: Deserializer<'de>>(
+  deserializer: D,
+) -> Result<Option<u16>, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Port {
+    Number(u16),
+    Text(String),
+  }
+
+  match Option::<Port>::deserialize(deserializer)? {
+    None => Ok(None),
+    Some(Port::Number(port)) => Ok(Some(port)),
+    Some(Port::Text(text)) if text.trim().is_empty() => Ok(None),
+    Some(Port::Text(text)) => text
+      .trim()
+      .parse()
+      .map(Some)
+      .map_err(|_| D::Error::custom(format!("expected a port number, got '{}'", text))),
+  }
+}
+
+pub fn config_path() -> PathBuf {
+  paths::data_dir().join("configuration.json")
 }
 
 pub fn get_config() -> Configuration {
-  let path = config_path();
-  let config = std::fs::read_to_string(path).unwrap_or("{}".to_string());
-  let config: Configuration = serde_json::from_str(&config).unwrap_or_default();
+  (*shared_config()).clone()
+}
+
+// Like get_config, without copying the whole configuration
+pub fn shared_config() -> Arc<Configuration> {
+  // Cleared first, so a change made while loading marks the result stale again
+  if !CACHE_STALE.swap(false, Ordering::AcqRel) {
+    if let Some(config) = CONFIG_CACHE.read().unwrap().as_ref() {
+      return config.clone();
+    }
+  }
+
+  // Checked like the watcher does, so a change it refused doesn't get in on the next reload.
+  // There's nothing better than the file to fall back to at first, so that's taken as it is.
+  let last_good = CONFIG_CACHE.read().unwrap().clone();
+  let loaded = load_config().and_then(|config| {
+    if let Some(last_good) = &last_good {
+      validate(&config, last_good)?;
+    }
+    Ok(config)
+  });
+
+  let config = match loaded {
+    Ok(config) => Arc::new(config),
+    Err(e) => {
+      // Kept until the file changes again, rather than re-reading a broken file on every request
+      warn!(
+        "Using the {} configuration: {}",
+        if last_good.is_some() {
+          "last good"
+        } else {
+          "default"
+        },
+        e
+      );
+      last_good.unwrap_or_default()
+    }
+  };
 
+  *CONFIG_CACHE.write().unwrap() = Some(config.clone());
   config
 }
+
+fn invalidate_cache() {
+  CACHE_STALE.store(true, Ordering::Release);
+}
+
+/**
+ * Watches configuration.json for changes made outside of Cultivation (by hand, or by a script).
+ * Valid changes replace the cached config and are sent to the frontend as `config_changed`.
+ * Invalid ones are logged and ignored, keeping the last good configuration.
+ */
+pub fn watch_config(app: tauri::AppHandle) {
+  thread::spawn(move || {
+    let path = config_path();
+    let Some(dir) = path.parent().map(Path::to_path_buf) else {
+      return;
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(tx) {
+      Ok(watcher) => watcher,
+      Err(e) => {
+        warn!("Failed to create config watcher: {}", e);
+        return;
+      }
+    };
+
+    // The directory is watched because write_config replaces the file instead of writing to it
+    if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+      warn!("Failed to watch {:?}: {}", dir, e);
+      return;
+    }
+
+    let mut previous = shared_config();
+    let mut previous_value = serde_json::to_value(&*previous).ok();
+
+    while let Ok(event) = rx.recv() {
+      let touches_config = match event {
+        Ok(event) => event
+          .paths
+          .iter()
+          .any(|p| p.file_name() == path.file_name()),
+        Err(e) => {
+          warn!("Config watcher error: {}", e);
+          false
+        }
+      };
+      if !touches_config {
+        continue;
+      }
+
+      thread::sleep(WATCH_SETTLE_TIME);
+      while rx.try_recv().is_ok() {}
+
+      let config = match load_config().and_then(|config| {
+        validate(&config, &previous)?;
+        Ok(config)
+      }) {
+        Ok(config) => config,
+        Err(e) => {
+          warn!("Ignoring change to configuration.json: {}", e);
+          continue;
+        }
+      };
+
+      let value = serde_json::to_value(&config).ok();
+      if value == previous_value {
+        continue;
+      }
+
+      info!("configuration.json changed, reloading");
+      previous = Arc::new(config);
+      previous_value = value;
+      *CONFIG_CACHE.write().unwrap() = Some(previous.clone());
+      logging::apply_config();
+
+      if let Err(e) = app.emit_all("config_changed", &*previous) {
+        warn!("Failed to send config_changed: {}", e);
+      }
+    }
+  });
+}
+
+// Like get_config, but a broken file is an error instead of silently becoming the defaults.
+// This is also what the frontend reads, so it sees the same values as the backend.
+#[tauri::command]
+pub fn load_config() -> Result<Configuration, CultivationError> {
+  let (config, _) = layered_config()?;
+
+  serde_json::from_value(Value::Object(config)).map_err(|e| {
+    CultivationError::Config(format!("configuration.json has an invalid value: {}", e))
+  })
+}
+
+// Only what's in configuration.json, without --set, --profile or CULTIVATION_* on top.
+// Read this when building changes to write back, or the overrides would end up in the file.
+pub fn load_file_config() -> Result<Configuration, CultivationError> {
+  serde_json::from_value(load_raw_config()?).map_err(|e| {
+    CultivationError::Config(format!("configuration.json has an invalid value: {}", e))
+  })
+}
+
+/**
+ * Shows the configuration the backend is actually using, and where each value came from.
+ * Keys that nothing sets are reported as "default".
+ */
+#[tauri::command]
+pub fn get_effective_config() -> Result<EffectiveConfig, CultivationError> {
+  let config = load_config()?;
+  let (_, mut sources) = layered_config()?;
+
+  for key in config_keys() {
+    sources.entry(key).or_insert(ConfigSource::Default);
+  }
+
+  Ok(EffectiveConfig { config, sources })
+}
+
+/**
+ * Parses a `key=value` assignment from the command line and layers it over everything else.
+ * The value is taken as JSON if the field accepts that (eg. `true`, `443`), and as text otherwise.
+ */
+pub fn set_cli_override(assignment: &str) -> Result<(), CultivationError> {
+  let Some((key, text)) = assignment.split_once('=') else {
+    return Err(CultivationError::Config(format!(
+      "Expected key=value, got '{}'",
+      assignment
+    )));
+  };
+
+  let key = key.trim();
+  let value = parse_override(key, text)?;
+
+  info!("Overriding {} from the command line", key);
+  CLI_OVERRIDES.lock().unwrap().insert(key.to_string(), value);
+  invalidate_cache();
+
+  Ok(())
+}
+
+/**
+ * Layers `changes` over configuration.json until Cultivation exits or they're replaced,
+ * without touching the file. The environment and --set still win.
+ */
+pub fn set_session_overrides(changes: Map<String, Value>) {
+  *SESSION_OVERRIDES.lock().unwrap() = changes;
+  invalidate_cache();
+}
+
+// Like set_session_overrides, keeping the ones already set that `changes` doesn't replace
+pub fn add_session_overrides(changes: Map<String, Value>) {
+  SESSION_OVERRIDES.lock().unwrap().extend(changes);
+  invalidate_cache();
+}
+
+// defaults < configuration.json < --profile < CULTIVATION_* variables < --set
+fn layered_config() -> Result<(Map<String, Value>, ConfigSources), CultivationError> {
+  let Value::Object(config) = load_raw_config()? else {
+    return Err(CultivationError::Config(
+      "configuration.json is not a JSON object".to_string(),
+    ));
+  };
+
+  Ok(apply_layers(
+    config,
+    [
+      (
+        ConfigSource::Profile,
+        SESSION_OVERRIDES.lock().unwrap().clone(),
+      ),
+      (
+        ConfigSource::Env,
+        env_overrides_from(|name| std::env::var(name).ok()),
+      ),
+      (ConfigSource::Cli, CLI_OVERRIDES.lock().unwrap().clone()),
+    ],
+  ))
+}
+
+// Each layer in `layers` wins over the file and the layers before it
+fn apply_layers(
+  mut config: Map<String, Value>,
+  layers: [(ConfigSource, Map<String, Value>); 3],
+) -> (Map<String, Value>, ConfigSources) {
+  let mut sources: ConfigSources = config
+    .keys()
+    .map(|key| (key.clone(), ConfigSource::File))
+    .collect();
+
+  for (source, overrides) in layers {
+    for (key, value) in overrides {
+      sources.insert(key.clone(), source);
+      config.insert(key, value);
+    }
+  }
+
+  (config, sources)
+}
+
+// The CULTIVATION_* variables, as looked up with `var`
+fn env_overrides_from(var: impl Fn(&str) -> Option<String>) -> Map<String, Value> {
+  let mut overrides = Map::new();
+
+  for key in config_keys() {
+    let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+    let Some(text) = var(&name) else {
+      continue;
+    };
+
+    // A bad variable shouldn't take the rest of the configuration down with it
+    match parse_override(&key, &text) {
+      Ok(value) => {
+        overrides.insert(key, value);
+      }
+      Err(e) => warn!("Ignoring {}: {}", name, e),
+    }
+  }
+
+  overrides
+}
+
+fn parse_override(key: &str, text: &str) -> Result<Value, CultivationError> {
+  if !config_keys().iter().any(|known| known == key) {
+    return Err(CultivationError::Config(format!(
+      "Unknown configuration key '{}'",
+      key
+    )));
+  }
+
+  // JSON first, so `443` is a number and `true` a bool, then as text for everything else
+  let mut candidates = vec![];
+  if let Ok(value) = serde_json::from_str(text) {
+    candidates.push(value);
+  }
+  candidates.push(Value::String(text.to_string()));
+
+  candidates
+    .into_iter()
+    .find(|value| serde_json::from_value::<Configuration>(json!({ key: value })).is_ok())
+    .ok_or_else(|| CultivationError::Config(format!("'{}' is not a valid {}", text, key)))
+}
+
+// Every field `Configuration` models, taken from the struct itself so new fields are picked up
+fn config_keys() -> Vec<String> {
+  match serde_json::to_value(Configuration::default()) {
+    Ok(Value::Object(fields)) => fields.keys().cloned().collect(),
+    _ => vec![],
+  }
+}
+
+fn load_raw_config() -> Result<Value, CultivationError> {
+  let raw = match fs::read_to_string(config_path()) {
+    Ok(raw) => raw,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => "{}".to_string(),
+    Err(e) => return Err(e.into()),
+  };
+
+  serde_json::from_str(&raw).map_err(|e| {
+    CultivationError::Config(format!(
+      "configuration.json could not be parsed (line {}, column {}): {}",
+      e.line(),
+      e.column(),
+      e
+    ))
+  })
+}
+
+/**
+ * Brings configuration.json up to CONFIG_VERSION. Called once at startup, so reading the
+ * configuration never writes it.
+ */
+pub fn migrate_config_file() {
+  let mut config = match load_raw_config() {
+    Ok(config) => config,
+    Err(e) => {
+      warn!("Not migrating the configuration: {}", e);
+      return;
+    }
+  };
+
+  if migrate(&mut config) {
+    if let Err(e) = write_config(&config) {
+      warn!("Failed to save migrated configuration: {}", e);
+    }
+  }
+}
+
+/**
+ * Upgrades an older config to CONFIG_VERSION one step at a time.
+ * Configs from a newer version are left alone, so we don't mangle settings we don't understand.
+ * Returns whether anything was changed.
+ */
+fn migrate(config: &mut Value) -> bool {
+  let Some(object) = config.as_object_mut() else {
+    return false;
+  };
+
+  let version = object
+    .get("config_version")
+    .and_then(Value::as_u64)
+    .unwrap_or(0);
+  if version >= CONFIG_VERSION {
+    return false;
+  }
+
+  for (step, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+    info!(
+      "Migrating configuration from version {} to {}",
+      step,
+      step + 1
+    );
+    migration(object);
+  }
+  object.insert("config_version".to_string(), json!(CONFIG_VERSION));
+
+  true
+}
+
+// v0 -> v1: last_port was stored as a string
+fn migrate_ports_to_integers(config: &mut Map<String, Value>) {
+  let port = match config.get("last_port") {
+    Some(Value::String(port)) => port.trim().parse::<u16>().map_or(Value::Null, Value::from),
+    _ => return,
+  };
+
+  config.insert("last_port".to_string(), port);
+}
+
+#[tauri::command]
+pub fn set_config(config: Configuration) -> Result<(), CultivationError> {
+  let previous = load_config().unwrap_or_default();
+  validate(&config, &previous)?;
+
+  let mut value = serde_json::to_value(&config)?;
+  keep_overrides_out(&mut value);
+  write_config(&value)
+}
+
+/**
+ * The frontend saves back what it read, which includes --set, --profile and CULTIVATION_* values.
+ * Those are swapped for what the file had, unless they were changed before saving.
+ */
+fn keep_overrides_out(config: &mut Value) {
+  // A broken file is replaced whole, like before
+  let (Ok(file), Ok(effective), Ok((_, sources))) = (
+    load_raw_config(),
+    load_config().map(|config| serde_json::to_value(config).unwrap_or_default()),
+    layered_config(),
+  ) else {
+    return;
+  };
+  let Some(object) = config.as_object_mut() else {
+    return;
+  };
+  for (key, source) in sources {
+    if source == ConfigSource::File || object.get(&key) != effective.get(&key) {
+      continue;
+    }
+
+    match file.get(&key) {
+      Some(value) => object.insert(key, value.clone()),
+      None => object.remove(&key),
+    };
+  }
+}
+
+/**
+ * Merges `changes` (a JSON object of config keys) into the current configuration.
+ * Returns the resulting configuration.
+ */
+#[tauri::command]
+pub fn update_config(changes: Map<String, Value>) -> Result<Configuration, CultivationError> {
+  // Refuse to merge into a file we can't read, or the user's settings would be replaced
+  let mut raw = load_raw_config()?;
+  // In case saving the migration at startup failed, the version is stamped on writing
+  migrate(&mut raw);
+  let previous: Configuration = serde_json::from_value(raw.clone()).unwrap_or_default();
+
+  let Some(object) = raw.as_object_mut() else {
+    return Err(CultivationError::Config(
+      "configuration.json is not a JSON object".to_string(),
+    ));
+  };
+  object.extend(changes);
+
+  let config: Configuration = serde_json::from_value(Value::Object(object.clone()))
+    .map_err(|e| CultivationError::Config(format!("Invalid configuration value: {}", e)))?;
+  validate(&config, &previous)?;
+
+  // Store the port the way the current schema does, even if it was sent as text
+  if let Some(port) = config.last_port {
+    object.insert("last_port".to_string(), json!(port));
+  }
+
+  write_config(&raw)?;
+  Ok(config)
+}
+
+/**
+ * Replaces the whole configuration, eg. with an imported one. Older schemas are migrated first.
+ * Every path is checked, since none of them come from the current file.
+ */
+pub fn replace_config(mut config: Value) -> Result<Configuration, CultivationError> {
+  migrate(&mut config);
+
+  let parsed: Configuration = serde_json::from_value(config.clone())
+    .map_err(|e| CultivationError::Config(format!("Invalid configuration value: {}", e)))?;
+  validate(&parsed, &Configuration::default())?;
+
+  write_config(&config)?;
+  Ok(parsed)
+}
+
+/**
+ * Checks the fields we can check. Paths are only checked when they changed,
+ * so an old path that has since disappeared doesn't block unrelated edits.
+ */
+pub fn validate(config: &Configuration, previous: &Configuration) -> Result<(), CultivationError> {
+  if let Some(close_action) = config.close_action {
+    if close_action > MAX_CLOSE_ACTION {
+      return Err(CultivationError::Config(format!(
+        "close_action must be between 0 and {}, got {}",
+        MAX_CLOSE_ACTION, close_action
+      )));
+    }
+  }
+
+  if let Some(name) = &config.active_profile {
+    let exists = config
+      .profiles
+      .as_ref()
+      .map_or(false, |profiles| profiles.contains_key(name));
+    if !exists {
+      return Err(CultivationError::Config(format!(
+        "active_profile is '{}', but there is no profile with that name",
+        name
+      )));
+    }
+  }
+
+  for path in config.games.iter().flat_map(|games| games.values()) {
+    games::require_game(path)?;
+  }
+
+  // Like the paths below, a value that was already there is left alone
+  if let Some(launch_args) = &config.launch_args {
+    if previous.launch_args.as_ref() != Some(launch_args) {
+      system_helpers::parse_launch_args(launch_args)?;
+    }
+  }
+
+  let paths = [
+    (
+      "game_install_path",
+      &config.game_install_path,
+      &previous.game_install_path,
+    ),
+    (
+      "grasscutter_path",
+      &config.grasscutter_path,
+      &previous.grasscutter_path,
+    ),
+    ("java_path", &config.java_path, &previous.java_path),
+  ];
+  for (key, path, previous_path) in paths {
+    if let Some(path) = path {
+      if path != previous_path.as_deref().unwrap_or_default() {
+        check_path_exists(key, path)?;
+      }
+    }
+  }
+
+  // The frontend also stores tool paths (migoto_path, akebi_path, ...)
+  for (key, value) in &config.extra {
+    if !key.ends_with("_path") {
+      continue;
+    }
+
+    if let Some(path) = value.as_str() {
+      if previous.extra.get(key) != Some(value) {
+        check_path_exists(key, path)?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+fn check_path_exists(key: &str, path: &str) -> Result<(), CultivationError> {
+  if path.is_empty() || Path::new(path).exists() {
+    Ok(())
+  } else {
+    Err(CultivationError::Config(format!(
+      "{} points to {}, which does not exist",
+      key, path
+    )))
+  }
+}
+
+/**
+ * Writes the config to a temporary file and renames it over the real one,
+ * so a crash mid-write can't leave a truncated configuration.json behind.
+ * The previous file is kept as configuration.json.bak.
+ */
+fn write_config(config: &Value) -> Result<(), CultivationError> {
+  let path = config_path();
+  let tmp_path = path.with_extension("json.tmp");
+  let bak_path = path.with_extension("json.bak");
+
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+
+  // Whatever was sent (the frontend leaves it out), it's in the current schema now
+  let mut config = config.clone();
+  if let Some(object) = config.as_object_mut() {
+    let version = object.get("config_version").and_then(Value::as_u64);
+    if version.map_or(true, |version| version < CONFIG_VERSION) {
+      object.insert("config_version".to_string(), json!(CONFIG_VERSION));
+    }
+  }
+
+  {
+    let mut tmp = fs::File::create(&tmp_path)?;
+    tmp.write_all(serde_json::to_string(&config)?.as_bytes())?;
+    tmp.sync_all()?;
+  }
+
+  if path.exists() {
+    fs::copy(&path, &bak_path)?;
+  }
+  fs::rename(&tmp_path, &path)?;
+  invalidate_cache();
+
+  info!("Saved configuration to {:?}", path);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn object(value: Value) -> Map<String, Value> {
+    match value {
+      Value::Object(object) => object,
+      _ => panic!("not an object"),
+    }
+  }
+
+  fn config(value: Value) -> Configuration {
+    serde_json::from_value(value).unwrap()
+  }
+
+  #[test]
+  fn overrides_are_json_before_text() {
+    assert_eq!(parse_override("last_port", "443").unwrap(), json!(443));
+    assert_eq!(
+      parse_override("debug_enabled", "true").unwrap(),
+      json!(true)
+    );
+    assert_eq!(
+      parse_override("last_ip", "localhost").unwrap(),
+      json!("localhost")
+    );
+    // Valid JSON, but not for this field
+    assert_eq!(parse_override("language", "123").unwrap(), json!("123"));
+    assert_eq!(
+      parse_override("launch_args", r#"-monitor "2""#).unwrap(),
+      json!(r#"-monitor "2""#)
+    );
+  }
+
+  #[test]
+  fn refuses_unknown_keys_and_bad_values() {
+    assert!(parse_override("no_such_key", "1").is_err());
+    assert!(parse_override("last_port", "not a port").is_err());
+    assert!(parse_override("debug_enabled", "[]").is_err());
+  }
+
+  #[test]
+  fn layers_win_in_order() {
+    let file = object(json!({ "last_ip": "file", "last_port": 1, "theme": "file" }));
+    let (config, sources) = apply_layers(
+      file,
+      [
+        (
+          ConfigSource::Profile,
+          object(json!({ "last_ip": "profile", "last_port": 2 })),
+        ),
+        (
+          ConfigSource::Env,
+          object(json!({ "last_ip": "env", "debug_enabled": true })),
+        ),
+        (ConfigSource::Cli, object(json!({ "last_ip": "cli" }))),
+      ],
+    );
+
+    assert_eq!(config["last_ip"], "cli");
+    assert_eq!(config["last_port"], 2);
+    assert_eq!(config["debug_enabled"], true);
+    assert_eq!(config["theme"], "file");
+    assert_eq!(sources["last_ip"], ConfigSource::Cli);
+    assert_eq!(sources["last_port"], ConfigSource::Profile);
+    assert_eq!(sources["debug_enabled"], ConfigSource::Env);
+    assert_eq!(sources["theme"], ConfigSource::File);
+  }
+
+  #[test]
+  fn reads_prefixed_variables_and_skips_bad_ones() {
+    let overrides = env_overrides_from(|name| match name {
+      "CULTIVATION_LAST_PORT" => Some("443".to_string()),
+      "CULTIVATION_LAST_IP" => Some("127.0.0.1".to_string()),
+      "CULTIVATION_DEBUG_ENABLED" => Some("yes".to_string()),
+      _ => None,
+    });
+
+    assert_eq!(
+      Value::Object(overrides),
+      json!({ "last_port": 443, "last_ip": "127.0.0.1" })
+    );
+  }
+
+  #[test]
+  fn migrates_ports_to_integers() {
+    let mut config = json!({ "last_port": " 443 ", "last_ip": "localhost" });
+    assert!(migrate(&mut config));
+    assert_eq!(
+      config,
+      json!({ "last_port": 443, "last_ip": "localhost", "config_version": 1 })
+    );
+
+    let mut config = json!({ "last_port": "junk" });
+    assert!(migrate(&mut config));
+    assert_eq!(config["last_port"], Value::Null);
+  }
+
+  #[test]
+  fn leaves_current_and_newer_configs_alone() {
+    let mut config = json!({ "config_version": CONFIG_VERSION, "last_port": "443" });
+    assert!(!migrate(&mut config));
+    assert_eq!(config["last_port"], "443");
+
+    let mut config = json!({ "config_version": CONFIG_VERSION + 1, "last_port": "443" });
+    assert!(!migrate(&mut config));
+    assert_eq!(config["config_version"], CONFIG_VERSION + 1);
+  }
+
+  #[test]
+  fn validates_close_action_and_profiles() {
+    let previous = Configuration::default();
+
+    assert!(validate(&config(json!({ "close_action": 1 })), &previous).is_ok());
+    assert!(validate(&config(json!({ "close_action": 2 })), &previous).is_err());
+    assert!(validate(&config(json!({ "active_profile": "missing" })), &previous).is_err());
+    assert!(validate(
+      &config(json!({ "active_profile": "home", "profiles": { "home": { "host": "localhost" } } })),
+      &previous
+    )
+    .is_ok());
+  }
+
+  #[test]
+  fn validates_paths_and_launch_args_only_when_they_change() {
+    let missing = json!({
+      "game_install_path": "/no/such/GenshinImpact.exe",
+      "launch_args": "-monitor \"2",
+    });
+    let previous = config(missing.clone());
+
+    assert!(validate(&config(missing.clone()), &previous).is_ok());
+    assert!(validate(&config(missing), &Configuration::default()).is_err());
+    assert!(validate(
+      &config(json!({ "launch_args": "-monitor \"2" })),
+      &Configuration::default()
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn validates_registered_games() {
+    let previous = Configuration::default();
+
+    assert!(validate(
+      &config(json!({ "games": { "cn": "/games/YuanShen.exe" } })),
+      &previous
+    )
+    .is_ok());
+    assert!(validate(
+      &config(json!({ "games": { "other": "/games/notepad.exe" } })),
+      &previous
+    )
+    .is_err());
+  }
+}
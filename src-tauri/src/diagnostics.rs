@@ -0,0 +1,377 @@
+use crate::config;
+use crate::error::CultivationError;
+use crate::file_helpers;
+use crate::game_version;
+use crate::gamebanana;
+use crate::games;
+use crate::logging;
+use crate::patch;
+use crate::paths;
+use crate::proxy;
+use crate::system_helpers;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use zip::write::FileOptions;
+
+// How many of the most recent log files go into the bundle
+const LOG_FILES_IN_BUNDLE: usize = 3;
+
+const REDACTED_HOST: &str = "<redacted host>";
+
+// Hosts in urls ("Set server to http://...") and bare IPv4 addresses
+static HOST_IN_TEXT: Lazy<Regex> = Lazy::new(|| {
+  Regex::new(r"(?i)\b([a-z][a-z0-9+.-]*://)([^/\s:@<>]+)|\b(\d{1,3}(?:\.\d{1,3}){3})\b").unwrap()
+});
+
+/**
+ * Zips everything we usually ask for in a bug report into one archive.
+ * Returns the path of the written archive.
+ */
+#[tauri::command]
+pub async fn export_diagnostics(
+  path: Option<String>,
+  redact: Option<bool>,
+) -> Result<String, CultivationError> {
+  let redact = redact.unwrap_or(true);
+  let archive_path = match path {
+    Some(path) => PathBuf::from(path),
+    None => default_archive_path(),
+  };
+
+  let config = config::get_config();
+  let mut report = json!({
+    "cultivation_version": env!("CARGO_PKG_VERSION"),
+    "platform": system_helpers::get_platform(),
+    "arch": std::env::consts::ARCH,
+    "data_dir": paths::data_dir(),
+    "portable": paths::is_portable(),
+    "proxy": proxy::proxy_status(),
+    "patch": patch::patch_state_description().await,
+    "game_version": game_version::game_exe()
+      .and_then(|exe| game_version::detect_game_version(&exe))
+      .map_or_else(|e| e.to_string(), |version| version.to_string()),
+    "ca_certificates": ca_fingerprints(),
+    "java_version": java_version(config.java_path.as_deref()),
+    "mods": installed_mods(config.game_install_path.clone()).await,
+  });
+
+  // The raw file is used instead of `Configuration` so fields the frontend added are kept
+  let raw = fs::read_to_string(config::config_path()).unwrap_or_else(|_| "{}".to_string());
+  let mut config_value = serde_json::from_str(&raw)
+    .unwrap_or_else(|e: serde_json::Error| json!({ "parse_error": e.to_string() }));
+
+  let redactor = redact.then(|| Redactor::new(&config_value));
+  if let Some(redactor) = &redactor {
+    redactor.redact_json("", &mut report, false);
+    redactor.redact_json("", &mut config_value, false);
+  }
+
+  let mut zip = zip::ZipWriter::new(File::create(&archive_path)?);
+  let options = FileOptions::default();
+
+  zip.start_file("report.json", options)?;
+  zip.write_all(serde_json::to_string_pretty(&report)?.as_bytes())?;
+
+  zip.start_file("configuration.json", options)?;
+  zip.write_all(serde_json::to_string_pretty(&config_value)?.as_bytes())?;
+
+  let logs = logging::log_files(&logging::logs_dir());
+  for log in logs.iter().rev().take(LOG_FILES_IN_BUNDLE) {
+    let name = log.file_name().unwrap_or_default().to_string_lossy();
+    let mut contents = String::from_utf8_lossy(&fs::read(log)?).to_string();
+    if let Some(redactor) = &redactor {
+      contents = contents
+        .lines()
+        .map(|line| redactor.redact_log_line(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    }
+
+    zip.start_file(format!("logs/{}", name), options)?;
+    zip.write_all(contents.as_bytes())?;
+  }
+
+  zip.finish()?;
+
+  info!("Wrote diagnostics to {:?}", archive_path);
+  Ok(archive_path.to_string_lossy().to_string())
+}
+
+fn default_archive_path() -> PathBuf {
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+
+  paths::data_dir().join(format!("diagnostics-{}.zip", timestamp))
+}
+
+/**
+ * Hides what identifies the user or their server: hosts, the folders things are installed in,
+ * and hooks, which can carry tokens. File names are kept, they're usually what we need to know.
+ */
+struct Redactor {
+  // Replaced wherever they show up, longest first so a folder goes before its parent
+  secrets: Vec<(String, String)>,
+  // Ours and the game's, they say nothing about the user
+  public_hosts: Vec<&'static str>,
+}
+
+impl Redactor {
+  fn new(config: &Value) -> Self {
+    let mut redactor = Redactor {
+      secrets: vec![],
+      public_hosts: games::redirect_domains(true),
+    };
+
+    if let Some(home) = tauri::api::path::home_dir() {
+      redactor.add_secret(home.to_string_lossy(), "~");
+    }
+    redactor.add_secret(paths::data_dir().to_string_lossy(), "<data dir>");
+    redactor.add_host(&host_of(&proxy::proxy_status().server));
+    redactor.collect_secrets("", config);
+
+    redactor
+      .secrets
+      .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+    redactor
+  }
+
+  fn add_secret(&mut self, secret: impl Into<String>, replacement: &str) {
+    let secret = secret.into();
+    // Too short to replace without mangling everything else
+    if secret.len() <= 3 {
+      return;
+    }
+
+    // Paths logged with {:?} have their backslashes escaped
+    if secret.contains('\\') {
+      self
+        .secrets
+        .push((secret.replace('\\', "\\\\"), replacement.to_string()));
+    }
+    self.secrets.push((secret, replacement.to_string()));
+  }
+
+  fn add_host(&mut self, host: &str) {
+    if !self.is_public_host(host) {
+      self.add_secret(host, REDACTED_HOST);
+    }
+  }
+
+  // The hosts and folders in the config, so they're also caught where the logs mention them
+  fn collect_secrets(&mut self, key: &str, value: &Value) {
+    match value {
+      Value::Object(map) => {
+        for (key, value) in map {
+          self.collect_secrets(key, value);
+        }
+      }
+      Value::Array(items) => {
+        for item in items {
+          self.collect_secrets(key, item);
+        }
+      }
+      Value::String(text) if is_host_key(key) => self.add_host(&host_of(text)),
+      Value::String(text) if looks_like_path(text) => {
+        if let Some((folder, _)) = text.rsplit_once(['/', '\\']) {
+          self.add_secret(folder, "<redacted>");
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn redact_json(&self, key: &str, value: &mut Value, in_hooks: bool) {
+    let in_hooks = in_hooks || key == "hooks";
+
+    match value {
+      Value::Object(map) => {
+        for (key, value) in map.iter_mut() {
+          self.redact_json(key, value, in_hooks);
+        }
+      }
+      Value::Array(items) => {
+        for item in items {
+          self.redact_json(key, item, in_hooks);
+        }
+      }
+      Value::String(text) if text.is_empty() => {}
+      Value::String(_) if in_hooks => *value = json!("<redacted hook>"),
+      Value::String(text) if is_host_key(key) => {
+        if !self.is_public_host(&host_of(text)) {
+          *value = json!(REDACTED_HOST);
+        }
+      }
+      Value::String(text) if looks_like_path(text) || text.contains(['/', '\\']) => {
+        // Windows paths too, whatever we're running on
+        let file_name = text.rsplit(['/', '\\']).next().unwrap_or_default();
+        *value = json!(format!("<redacted>/{}", file_name));
+      }
+      Value::String(text) => *text = self.redact_text(text),
+      _ => {}
+    }
+  }
+
+  fn redact_log_line(&self, line: &str) -> String {
+    // Hooks log their command lines and output, which is theirs to keep
+    match line.find("cultivation::hooks:") {
+      Some(at) => format!("{}cultivation::hooks: <redacted hook output>", &line[..at]),
+      None => self.redact_text(line),
+    }
+  }
+
+  fn redact_text(&self, text: &str) -> String {
+    let mut text = text.to_string();
+    for (secret, replacement) in &self.secrets {
+      text = text.replace(secret.as_str(), replacement);
+    }
+
+    HOST_IN_TEXT
+      .replace_all(&text, |captures: &Captures| {
+        let host = captures.get(2).or_else(|| captures.get(3)).unwrap();
+        if self.is_public_host(host.as_str()) {
+          captures[0].to_string()
+        } else {
+          let scheme = captures.get(1).map_or("", |scheme| scheme.as_str());
+          format!("{}{}", scheme, REDACTED_HOST)
+        }
+      })
+      .to_string()
+  }
+
+  fn is_public_host(&self, host: &str) -> bool {
+    host.is_empty()
+      || host == "localhost"
+      || host.starts_with("127.")
+      || self
+        .public_hosts
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+  }
+}
+
+fn is_host_key(key: &str) -> bool {
+  matches!(key, "last_ip" | "host" | "server")
+}
+
+// "http://example.com:443" -> "example.com", a bare host is returned as is
+fn host_of(text: &str) -> String {
+  let text = text.trim();
+  let without_scheme = text.split_once("://").map_or(text, |(_, rest)| rest);
+  without_scheme
+    .split(|c| c == '/' || c == ':')
+    .next()
+    .unwrap_or_default()
+    .to_string()
+}
+
+fn looks_like_path(text: &str) -> bool {
+  let bytes = text.as_bytes();
+  let windows_drive = bytes.len() > 2
+    && bytes[0].is_ascii_alphabetic()
+    && bytes[1] == b':'
+    && (bytes[2] == b'\\' || bytes[2] == b'/');
+
+  text.starts_with('/') || text.starts_with(r"\\") || windows_drive
+}
+
+fn ca_fingerprints() -> Value {
+  let mut ca_files = vec![paths::data_dir().join("ca").join("cert.crt")];
+
+  #[cfg(target_os = "linux")]
+  ca_files.push(PathBuf::from(
+    "/usr/local/share/ca-certificates/cultivation.crt",
+  ));
+
+  let mut fingerprints = serde_json::Map::new();
+  for ca_file in ca_files {
+    let fingerprint = match fs::read(&ca_file) {
+      Ok(pem) => match rustls_pemfile::certs(&mut pem.as_slice()) {
+        Ok(certs) if !certs.is_empty() => json!(file_helpers::sha256_bytes(&certs[0])),
+        _ => json!("<not a certificate>"),
+      },
+      Err(_) => Value::Null,
+    };
+
+    fingerprints.insert(ca_file.to_string_lossy().to_string(), fingerprint);
+  }
+
+  // Windows keeps the installed copy in the user's Root store
+  #[cfg(windows)]
+  fingerprints.insert(
+    "certutil".to_string(),
+    json!(command_output(Command::new("certutil").args([
+      "-user",
+      "-store",
+      "Root",
+      "Cultivation"
+    ]))),
+  );
+
+  Value::Object(fingerprints)
+}
+
+fn java_version(java_path: Option<&str>) -> String {
+  let java = match java_path {
+    Some(path) if !path.is_empty() => path,
+    _ => "java",
+  };
+
+  command_output(Command::new(java).arg("-version"))
+}
+
+// Java prints its version to stderr, so both streams are kept
+fn command_output(command: &mut Command) -> String {
+  match command.output() {
+    Ok(output) => format!(
+      "{}{}",
+      String::from_utf8_lossy(&output.stdout),
+      String::from_utf8_lossy(&output.stderr)
+    )
+    .trim()
+    .to_string(),
+    Err(e) => format!("Failed to run {:?}: {}", command, e),
+  }
+}
+
+async fn installed_mods(game_install_path: Option<String>) -> Vec<String> {
+  let Some(game_install_path) = game_install_path else {
+    return vec![];
+  };
+
+  match gamebanana::list_mods(game_install_path).await {
+    Ok(mods) => {
+      let mut names: Vec<String> = mods
+        .keys()
+        .map(|path| {
+          // Mods with a modinfo.json are keyed by that file rather than their folder
+          let path = Path::new(path);
+          let folder = match path.file_name() {
+            Some(name) if name == "modinfo.json" => path.parent().unwrap_or(path),
+            _ => path,
+          };
+          folder
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+        })
+        .collect();
+      names.sort();
+      names
+    }
+    Err(e) => {
+      warn!("Failed to list mods for diagnostics: {}", e);
+      vec![]
+    }
+  }
+}
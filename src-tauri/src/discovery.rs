@@ -0,0 +1,374 @@
+use crate::error::CultivationError;
+use crate::game_version::{self, GameVersion};
+use crate::games;
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+#[cfg(windows)]
+use registry::{Data, Hive, RegKey, Security};
+
+#[cfg(target_os = "linux")]
+use {
+  anime_launcher_sdk::{config::ConfigExt, genshin::config::Config},
+  regex::Regex,
+  serde_json::Value,
+};
+
+// Where the official launchers install to, under Program Files
+const LAUNCHER_DIRS: [&str; 4] = [
+  "Genshin Impact",
+  "原神",
+  "HoYoPlay/games",
+  "miHoYo Launcher/games",
+];
+
+#[derive(Serialize, Debug)]
+pub struct GameInstall {
+  // The executable, to be saved as game_install_path
+  pub path: PathBuf,
+  pub exe_name: String,
+  pub version: Option<GameVersion>,
+  // Where it was found, eg. "registry", "aagl" or "steam"
+  pub source: &'static str,
+}
+
+/**
+ * Looks for the game in the usual places: the launchers' registry entries and install folders on
+ * Windows, and the AAGL config, Steam, Lutris and Heroic libraries and Wine prefixes on Linux.
+ * Nothing is saved, first-run setup lets the user pick one of these.
+ */
+#[tauri::command]
+pub async fn discover_game_installs() -> Result<Vec<GameInstall>, CultivationError> {
+  tauri::async_runtime::spawn_blocking(discover)
+    .await
+    .map_err(|e| CultivationError::Io(format!("Looking for the game was interrupted: {}", e)))
+}
+
+pub fn discover() -> Vec<GameInstall> {
+  let mut seen = HashSet::new();
+  let mut installs = vec![];
+
+  for (source, dir) in candidate_dirs() {
+    for exe in find_game_exes(&dir) {
+      // The same install is often reachable through a few of these
+      if !seen.insert(exe.canonicalize().unwrap_or_else(|_| exe.clone())) {
+        continue;
+      }
+
+      debug!("Found the game at {:?} ({})", exe, source);
+      installs.push(GameInstall {
+        exe_name: exe.file_name().unwrap().to_string_lossy().to_string(),
+        version: game_version::detect_game_version(&exe).ok(),
+        path: exe,
+        source,
+      });
+    }
+  }
+
+  info!("Found {} game installs", installs.len());
+  installs
+}
+
+// The executable in `dir`, or a folder below it like the launchers' "Genshin Impact game"
+fn find_game_exes(dir: &Path) -> Vec<PathBuf> {
+  let mut dirs = vec![dir.to_path_buf()];
+  dirs.extend(subdirs(dir));
+
+  dirs
+    .iter()
+    .flat_map(|dir| {
+      games::executable_names()
+        .into_iter()
+        .map(move |exe| dir.join(exe))
+    })
+    .filter(|exe| exe.is_file())
+    .collect()
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return vec![];
+  };
+
+  entries
+    .flatten()
+    .map(|entry| entry.path())
+    .filter(|path| path.is_dir())
+    .collect()
+}
+
+// The launcher folders inside a Wine prefix
+#[cfg(not(windows))]
+fn prefix_dirs(prefix: &Path) -> Vec<PathBuf> {
+  let drive_c = prefix.join("drive_c");
+  let mut dirs = vec![];
+
+  for program_files in ["Program Files", "Program Files (x86)"] {
+    dirs.extend(
+      LAUNCHER_DIRS
+        .iter()
+        .map(|dir| drive_c.join(program_files).join(dir)),
+    );
+  }
+
+  dirs
+}
+
+#[cfg(windows)]
+fn candidate_dirs() -> Vec<(&'static str, PathBuf)> {
+  let mut dirs: Vec<_> = registry_dirs()
+    .into_iter()
+    .map(|dir| ("registry", dir))
+    .collect();
+
+  for var in ["ProgramFiles", "ProgramFiles(x86)"] {
+    if let Some(program_files) = std::env::var_os(var) {
+      let program_files = PathBuf::from(program_files);
+      dirs.extend(
+        LAUNCHER_DIRS
+          .iter()
+          .map(|dir| ("launcher", program_files.join(dir))),
+      );
+    }
+  }
+
+  dirs
+}
+
+// The launchers register an uninstaller that knows where the game went, HoYoPlay keeps its own key
+#[cfg(windows)]
+fn registry_dirs() -> Vec<PathBuf> {
+  let mut dirs = vec![];
+
+  for hive in [Hive::LocalMachine, Hive::CurrentUser] {
+    let Ok(uninstall) = hive.open(
+      r"Software\Microsoft\Windows\CurrentVersion\Uninstall",
+      Security::Read,
+    ) else {
+      continue;
+    };
+
+    for key in uninstall.keys().flatten() {
+      let Ok(key) = key.open(Security::Read) else {
+        continue;
+      };
+
+      let name = registry_string(&key, "DisplayName").unwrap_or_default();
+      if name.contains("原神") || games::GAMES.iter().any(|game| name.contains(game.name)) {
+        dirs.extend(
+          ["InstallPath", "InstallLocation"]
+            .iter()
+            .filter_map(|value| registry_string(&key, value))
+            .map(PathBuf::from),
+        );
+      }
+    }
+  }
+
+  for path in [
+    r"Software\Cognosphere\HYP\1_0\hk4e_global",
+    r"Software\miHoYo\HYP\1_0\hk4e_cn",
+  ] {
+    if let Ok(key) = Hive::CurrentUser.open(path, Security::Read) {
+      dirs.extend(registry_string(&key, "GameInstallPath").map(PathBuf::from));
+    }
+  }
+
+  dirs
+}
+
+#[cfg(windows)]
+fn registry_string(key: &RegKey, name: &str) -> Option<String> {
+  match key.value(name).ok()? {
+    Data::String(value) | Data::ExpandString(value) => Some(value.to_string_lossy()),
+    _ => None,
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn candidate_dirs() -> Vec<(&'static str, PathBuf)> {
+  let home = tauri::api::path::home_dir().unwrap_or_default();
+  let mut dirs = vec![];
+
+  match Config::get() {
+    Ok(config) => {
+      dirs.push(("aagl", config.game.path.global.clone()));
+      dirs.push(("aagl", config.game.path.china.clone()));
+      dirs.extend(
+        prefix_dirs(&config.game.wine.prefix)
+          .into_iter()
+          .map(|dir| ("aagl", dir)),
+      );
+    }
+    Err(e) => debug!("Not looking in AAGL, its config could not be read: {}", e),
+  }
+
+  for library in steam_libraries(&home) {
+    let steamapps = library.join("steamapps");
+    dirs.extend(
+      subdirs(&steamapps.join("common"))
+        .into_iter()
+        .map(|dir| ("steam", dir)),
+    );
+
+    // Non-Steam games run with Proton get a prefix here too
+    for compat in subdirs(&steamapps.join("compatdata")) {
+      dirs.extend(
+        prefix_dirs(&compat.join("pfx"))
+          .into_iter()
+          .map(|dir| ("steam", dir)),
+      );
+    }
+  }
+
+  dirs.extend(lutris_dirs(&home).into_iter().map(|dir| ("lutris", dir)));
+  dirs.extend(heroic_dirs(&home).into_iter().map(|dir| ("heroic", dir)));
+
+  // Plain Wine, and ~/Games where Lutris and Heroic install to by default
+  let mut prefixes = vec![home.join(".wine")];
+  prefixes.extend(subdirs(&home.join("Games")));
+  prefixes.extend(subdirs(&home.join("Games/Heroic/Prefixes")));
+  for prefix in prefixes {
+    dirs.push(("wine_prefix", prefix.clone()));
+    dirs.extend(
+      prefix_dirs(&prefix)
+        .into_iter()
+        .map(|dir| ("wine_prefix", dir)),
+    );
+  }
+
+  dirs
+}
+
+// Steam's own folder, and every other library listed in its libraryfolders.vdf
+#[cfg(target_os = "linux")]
+fn steam_libraries(home: &Path) -> Vec<PathBuf> {
+  let roots = [
+    home.join(".steam/steam"),
+    home.join(".local/share/Steam"),
+    home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"),
+  ];
+  let library_path = Regex::new(r#""path"\s+"([^"]+)""#).unwrap();
+
+  let mut libraries = vec![];
+  for root in roots {
+    if let Ok(vdf) = fs::read_to_string(root.join("steamapps/libraryfolders.vdf")) {
+      libraries.extend(
+        library_path
+          .captures_iter(&vdf)
+          .map(|captures| PathBuf::from(&captures[1])),
+      );
+    }
+    libraries.push(root);
+  }
+
+  libraries
+}
+
+// Lutris keeps a yml per game, we only need its exe and prefix lines
+#[cfg(target_os = "linux")]
+fn lutris_dirs(home: &Path) -> Vec<PathBuf> {
+  let config_dirs = [
+    home.join(".config/lutris/games"),
+    home.join(".local/share/lutris/games"),
+    home.join(".var/app/net.lutris.Lutris/config/lutris/games"),
+  ];
+
+  let mut dirs = vec![];
+  let files = config_dirs
+    .iter()
+    .flat_map(|dir| fs::read_dir(dir).into_iter().flatten().flatten())
+    .map(|entry| entry.path());
+  for file in files {
+    let Ok(raw) = fs::read_to_string(&file) else {
+      continue;
+    };
+
+    for line in raw.lines() {
+      let Some((key, value)) = line.trim().split_once(':') else {
+        continue;
+      };
+      let value = PathBuf::from(value.trim().trim_matches(|c| c == '\'' || c == '"'));
+
+      match key {
+        "exe" => dirs.extend(value.parent().map(Path::to_path_buf)),
+        "game_path" => dirs.push(value),
+        "prefix" => dirs.extend(prefix_dirs(&value)),
+        _ => {}
+      }
+    }
+  }
+
+  dirs
+}
+
+// Heroic keeps each game's wine prefix in GamesConfig, and sideloaded games in their own library
+#[cfg(target_os = "linux")]
+fn heroic_dirs(home: &Path) -> Vec<PathBuf> {
+  let config_dirs = [
+    home.join(".config/heroic"),
+    home.join(".var/app/com.heroicgameslauncher.hgl/config/heroic"),
+  ];
+
+  let mut files = vec![];
+  for config_dir in &config_dirs {
+    files.push(config_dir.join("sideload_apps/library.json"));
+    files.extend(
+      fs::read_dir(config_dir.join("GamesConfig"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path()),
+    );
+  }
+
+  let mut dirs = vec![];
+  for file in files {
+    let Ok(raw) = fs::read_to_string(&file) else {
+      continue;
+    };
+    let Ok(json) = serde_json::from_str::<Value>(&raw) else {
+      debug!("Skipping {:?}, it's not valid json", file);
+      continue;
+    };
+
+    for prefix in json_strings(&json, "winePrefix") {
+      dirs.extend(prefix_dirs(Path::new(&prefix)));
+    }
+    for exe in json_strings(&json, "executable") {
+      dirs.extend(Path::new(&exe).parent().map(Path::to_path_buf));
+    }
+  }
+
+  dirs
+}
+
+// Every string under `key`, however deep
+#[cfg(target_os = "linux")]
+fn json_strings(json: &Value, key: &str) -> Vec<String> {
+  match json {
+    Value::Object(map) => map
+      .iter()
+      .flat_map(|(k, v)| match v {
+        Value::String(s) if k == key => vec![s.clone()],
+        _ => json_strings(v, key),
+      })
+      .collect(),
+    Value::Array(items) => items.iter().flat_map(|v| json_strings(v, key)).collect(),
+    _ => vec![],
+  }
+}
+
+#[cfg(target_os = "macos")]
+fn candidate_dirs() -> Vec<(&'static str, PathBuf)> {
+  let home = tauri::api::path::home_dir().unwrap_or_default();
+
+  prefix_dirs(&home.join(".wine"))
+    .into_iter()
+    .map(|dir| ("wine_prefix", dir))
+    .collect()
+}
@@ -1,3 +1,4 @@
+use crate::error::CultivationError;
 use once_cell::sync::Lazy;
 
 use std::cmp::min;
@@ -6,6 +7,7 @@ use std::io::Write;
 use std::sync::Mutex;
 
 use futures_util::StreamExt;
+use tracing::{debug, info, warn};
 
 // This will create a downloads list that will be used to check if we should continue downloading the file
 static DOWNLOADS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
@@ -13,13 +15,22 @@ static DOWNLOADS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new())
 // Lots of help from: https://gist.github.com/giuliano-oliveira/4d11d6b3bb003dba3a1b53f43d81b30d
 // and docs ofc
 #[tauri::command]
-pub async fn download_file(window: tauri::Window, url: &str, path: &str) -> Result<(), String> {
+pub async fn download_file(
+  window: tauri::Window,
+  url: &str,
+  path: &str,
+) -> Result<(), CultivationError> {
+  info!("Downloading {} to {}", url, path);
+
   // Reqwest setup
   let res = match reqwest::get(url).await {
     Ok(r) => r,
-    Err(_e) => {
+    Err(e) => {
       emit_download_err(window, format!("Failed to request {}", url), path);
-      return Err(format!("Failed to request {}", url));
+      return Err(CultivationError::Network(format!(
+        "Failed to request {}: {}",
+        url, e
+      )));
     }
   };
   let total_size = res.content_length().unwrap_or(0);
@@ -27,9 +38,9 @@ pub async fn download_file(window: tauri::Window, url: &str, path: &str) -> Resu
   // Create file path
   let mut file = match File::create(path) {
     Ok(f) => f,
-    Err(_e) => {
+    Err(e) => {
       emit_download_err(window, format!("Failed to create file '{}'", path), path);
-      return Err(format!("Failed to create file '{}'", path));
+      return Err(e.into());
     }
   };
   let mut downloaded: u64 = 0;
@@ -45,6 +56,7 @@ pub async fn download_file(window: tauri::Window, url: &str, path: &str) -> Resu
   while let Some(item) = stream.next().await {
     // Stop the loop if the download is removed from the list
     if !DOWNLOADS.lock().unwrap().contains(&path.to_string()) {
+      debug!("Download of {} was stopped", path);
       break;
     }
 
@@ -52,7 +64,7 @@ pub async fn download_file(window: tauri::Window, url: &str, path: &str) -> Resu
       Ok(itm) => itm,
       Err(e) => {
         emit_download_err(window, "Error while downloading file".to_string(), path);
-        return Err(format!("Error while downloading file: {}", e));
+        return Err(e.into());
       }
     };
     let vect = &chunk.to_vec()[..];
@@ -62,7 +74,7 @@ pub async fn download_file(window: tauri::Window, url: &str, path: &str) -> Resu
       Ok(x) => x,
       Err(e) => {
         emit_download_err(window, "Error while writing file".to_string(), path);
-        return Err(format!("Error while writing file: {}", e));
+        return Err(e.into());
       }
     }
 
@@ -84,6 +96,7 @@ pub async fn download_file(window: tauri::Window, url: &str, path: &str) -> Resu
   }
 
   // One more "finish" event
+  info!("Finished downloading {} ({} bytes)", path, total_downloaded);
   window.emit("download_finished", &path).unwrap();
 
   // We are done
@@ -91,6 +104,8 @@ pub async fn download_file(window: tauri::Window, url: &str, path: &str) -> Resu
 }
 
 pub fn emit_download_err(window: tauri::Window, msg: String, path: &str) {
+  warn!("{} ({})", msg, path);
+
   let mut res_hash = std::collections::HashMap::new();
 
   res_hash.insert("error".to_string(), msg);
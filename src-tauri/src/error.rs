@@ -0,0 +1,123 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+/// Error returned by every fallible Tauri command.
+///
+/// Serialized to the frontend as `{ "code": "...", "message": "..." }`, where
+/// `code` is stable and safe to branch on.
+#[derive(Debug)]
+pub enum CultivationError {
+  Io(String),
+  Network(String),
+  Archive(String),
+  Config(String),
+  Permission(String),
+  PlatformUnsupported(String),
+  Patch(String),
+  GameVersion(String),
+  Metadata(String),
+  RsaKey(String),
+  ForeignPatch(String),
+  Hook(String),
+}
+
+impl CultivationError {
+  pub fn code(&self) -> &'static str {
+    match self {
+      CultivationError::Io(_) => "io",
+      CultivationError::Network(_) => "network",
+      CultivationError::Archive(_) => "archive",
+      CultivationError::Config(_) => "config",
+      CultivationError::Permission(_) => "permission",
+      CultivationError::PlatformUnsupported(_) => "platform_unsupported",
+      CultivationError::Patch(_) => "patch",
+      CultivationError::GameVersion(_) => "game_version",
+      CultivationError::Metadata(_) => "metadata",
+      CultivationError::RsaKey(_) => "rsa_key",
+      CultivationError::ForeignPatch(_) => "foreign_patch",
+      CultivationError::Hook(_) => "hook",
+    }
+  }
+
+  pub fn message(&self) -> &str {
+    match self {
+      CultivationError::Io(msg)
+      | CultivationError::Network(msg)
+      | CultivationError::Archive(msg)
+      | CultivationError::Config(msg)
+      | CultivationError::Permission(msg)
+      | CultivationError::PlatformUnsupported(msg)
+      | CultivationError::Patch(msg)
+      | CultivationError::GameVersion(msg)
+      | CultivationError::Metadata(msg)
+      | CultivationError::RsaKey(msg)
+      | CultivationError::ForeignPatch(msg)
+      | CultivationError::Hook(msg) => msg,
+    }
+  }
+
+  // Shorthand for commands that have no implementation on the current OS
+  pub fn unsupported(what: &str) -> Self {
+    CultivationError::PlatformUnsupported(format!(
+      "{} is not supported on {}",
+      what,
+      std::env::consts::OS
+    ))
+  }
+}
+
+impl fmt::Display for CultivationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} error: {}", self.code(), self.message())
+  }
+}
+
+impl std::error::Error for CultivationError {}
+
+impl Serialize for CultivationError {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("CultivationError", 2)?;
+    state.serialize_field("code", self.code())?;
+    state.serialize_field("message", self.message())?;
+    state.end()
+  }
+}
+
+impl From<std::io::Error> for CultivationError {
+  fn from(e: std::io::Error) -> Self {
+    match e.kind() {
+      std::io::ErrorKind::PermissionDenied => CultivationError::Permission(e.to_string()),
+      _ => CultivationError::Io(e.to_string()),
+    }
+  }
+}
+
+impl From<reqwest::Error> for CultivationError {
+  fn from(e: reqwest::Error) -> Self {
+    CultivationError::Network(e.to_string())
+  }
+}
+
+impl From<zip::result::ZipError> for CultivationError {
+  fn from(e: zip::result::ZipError) -> Self {
+    CultivationError::Archive(e.to_string())
+  }
+}
+
+impl From<sevenz_rust::Error> for CultivationError {
+  fn from(e: sevenz_rust::Error) -> Self {
+    CultivationError::Archive(e.to_string())
+  }
+}
+
+impl From<serde_json::Error> for CultivationError {
+  fn from(e: serde_json::Error) -> Self {
+    CultivationError::Config(e.to_string())
+  }
+}
+
+impl From<ini::Error> for CultivationError {
+  fn from(e: ini::Error) -> Self {
+    CultivationError::Config(e.to_string())
+  }
+}
@@ -1,15 +1,19 @@
+use crate::error::CultivationError;
 use file_diff::diff;
+use md5::Md5;
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
 
 #[tauri::command]
-pub fn rename(path: String, new_name: String) {
+pub fn rename(path: String, new_name: String) -> Result<(), CultivationError> {
   let mut new_path = path.clone();
 
   // Check if file/folder to replace exists
   if fs::metadata(&path).is_err() {
-    return;
+    return Err(CultivationError::Io(format!("{} does not exist", path)));
   }
 
   // Check if path uses forward or back slashes
@@ -17,21 +21,18 @@ pub fn rename(path: String, new_name: String) {
     new_path = path.replace('\\', "/");
   }
 
-  let path_replaced = &path.replace(new_path.split('/').last().unwrap(), &new_name);
+  let path_replaced = &path.replace(new_path.split('/').last().unwrap_or_default(), &new_name);
 
-  match fs::rename(&path, path_replaced) {
-    Ok(_) => {
-      println!("Renamed {} to {}", &path, path_replaced);
-    }
-    Err(e) => {
-      println!("Error: {}", e);
-    }
-  };
+  fs::rename(&path, path_replaced)?;
+  debug!("Renamed {} to {}", &path, path_replaced);
+
+  Ok(())
 }
 
 #[tauri::command]
-pub fn dir_create(path: String) {
-  fs::create_dir_all(path).unwrap();
+pub fn dir_create(path: String) -> Result<(), CultivationError> {
+  fs::create_dir_all(path)?;
+  Ok(())
 }
 
 #[tauri::command]
@@ -41,15 +42,16 @@ pub fn dir_exists(path: &str) -> bool {
 }
 
 #[tauri::command]
-pub fn dir_is_empty(path: &str) -> bool {
+pub fn dir_is_empty(path: &str) -> Result<bool, CultivationError> {
   let path_buf = PathBuf::from(path);
-  fs::read_dir(path_buf).unwrap().count() == 0
+  Ok(fs::read_dir(path_buf)?.next().is_none())
 }
 
 #[tauri::command]
-pub fn dir_delete(path: &str) {
+pub fn dir_delete(path: &str) -> Result<(), CultivationError> {
   let path_buf = PathBuf::from(path);
-  fs::remove_dir_all(path_buf).unwrap();
+  fs::remove_dir_all(path_buf)?;
+  Ok(())
 }
 
 #[tauri::command]
@@ -57,113 +59,116 @@ pub fn are_files_identical(path1: &str, path2: &str) -> bool {
   diff(path1, path2)
 }
 
+// Lowercase hex SHA-256 of a file's contents
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String, CultivationError> {
+  let mut file = fs::File::open(path)?;
+  let mut hasher = Sha256::new();
+  std::io::copy(&mut file, &mut hasher)?;
+
+  Ok(to_hex(&hasher.finalize()))
+}
+
+// Lowercase hex MD5, which is what the game's pkg_version lists
+pub fn md5_file<P: AsRef<Path>>(path: P) -> Result<String, CultivationError> {
+  let mut file = fs::File::open(path)?;
+  let mut hasher = Md5::new();
+  std::io::copy(&mut file, &mut hasher)?;
+
+  Ok(to_hex(&hasher.finalize()))
+}
+
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+  to_hex(&Sha256::digest(bytes))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 #[tauri::command]
-pub fn copy_file(path: String, new_path: String) -> bool {
-  let filename = &path.split('/').last().unwrap();
+pub fn copy_file(path: String, new_path: String) -> Result<(), CultivationError> {
+  let filename = &path.split('/').last().unwrap_or_default();
   let path_buf = PathBuf::from(&path);
 
   // If the new path doesn't exist, create it.
   if !dir_exists(PathBuf::from(&new_path).pop().to_string().as_str()) {
-    std::fs::create_dir_all(&new_path).unwrap();
+    std::fs::create_dir_all(&new_path)?;
   }
 
   // Copy old to new
   match std::fs::copy(path_buf, format!("{}/{}", new_path, filename)) {
-    Ok(_) => true,
+    Ok(_) => Ok(()),
     Err(e) => {
-      println!("Failed to copy file: {}", e);
-      println!("Path: {}", path);
-      println!("New Path: {}", new_path);
-      false
+      warn!("Failed to copy file {} to {}: {}", path, new_path, e);
+      Err(e.into())
     }
   }
 }
 
 #[tauri::command]
-pub fn copy_file_with_new_name(path: String, new_path: String, new_name: String) -> bool {
+pub fn copy_file_with_new_name(
+  path: String,
+  new_path: String,
+  new_name: String,
+) -> Result<(), CultivationError> {
   let mut new_path_buf = PathBuf::from(&new_path);
   let path_buf = PathBuf::from(&path);
 
   // If the new path doesn't exist, create it.
   if !dir_exists(PathBuf::from(&new_path).pop().to_string().as_str()) {
-    match std::fs::create_dir_all(&new_path) {
-      Ok(_) => {}
-      Err(e) => {
-        println!("Failed to create directory: {}", e);
-        return false;
-      }
-    };
+    std::fs::create_dir_all(&new_path)?;
   }
 
   new_path_buf.push(new_name);
 
   // Copy old to new
   match std::fs::copy(path_buf, &new_path_buf) {
-    Ok(_) => true,
+    Ok(_) => Ok(()),
     Err(e) => {
-      println!("Failed to copy file: {}", e);
-      println!("Path: {}", path);
-      println!("New Path: {}", new_path);
-      false
+      warn!("Failed to copy file {} to {}: {}", path, new_path, e);
+      Err(e.into())
     }
   }
 }
 
 #[tauri::command]
-pub fn delete_file(path: String) -> bool {
+pub fn delete_file(path: String) -> Result<(), CultivationError> {
   let path_buf = PathBuf::from(&path);
 
-  match std::fs::remove_file(path_buf) {
-    Ok(_) => true,
-    Err(e) => {
-      println!("Failed to delete file: {}", e);
-      false
-    }
-  };
-
-  false
+  std::fs::remove_file(path_buf)?;
+  Ok(())
 }
 
 #[tauri::command]
-pub fn read_file(path: String) -> String {
+pub fn read_file(path: String) -> Result<String, CultivationError> {
   let path_buf = PathBuf::from(&path);
 
   let mut file = match fs::File::open(path_buf) {
     Ok(file) => file,
     Err(e) => {
-      println!("Failed to open file {}: {}", &path, e);
+      warn!("Failed to open file {}: {}", &path, e);
       if path.contains("config") {
         // Server.ts won't print the error so handle the message here for the user
-        println!("Server config not found or invalid. Be sure to run the server at least once to generate it before making edits.");
+        warn!("Server config not found or invalid. Be sure to run the server at least once to generate it before making edits.");
       }
-      return String::new(); // Send back error for handling by the caller
+      return Err(e.into());
     }
   };
 
   let mut contents = String::new();
-  file.read_to_string(&mut contents).unwrap();
+  file.read_to_string(&mut contents)?;
 
-  contents
+  Ok(contents)
 }
 
 #[tauri::command]
-pub fn write_file(path: String, contents: String) {
+pub fn write_file(path: String, contents: String) -> Result<(), CultivationError> {
   let path_buf = PathBuf::from(&path);
 
   // Create file if it exists, otherwise just open and rewrite
-  let mut file = match fs::File::create(path_buf) {
-    Ok(file) => file,
-    Err(e) => {
-      println!("Failed to open file: {}", e);
-      return;
-    }
-  };
+  let mut file = fs::File::create(path_buf)?;
 
   // Write contents to file
-  match file.write_all(contents.as_bytes()) {
-    Ok(_) => (),
-    Err(e) => {
-      println!("Failed to write to file: {}", e);
-    }
-  }
+  file.write_all(contents.as_bytes())?;
+  Ok(())
 }
@@ -0,0 +1,148 @@
+use crate::config;
+use crate::error::CultivationError;
+use crate::games;
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::debug;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GameVersion {
+  pub major: u32,
+  pub minor: u32,
+  pub release: u32,
+}
+
+// "3.1.0", or just "3.1"
+impl FromStr for GameVersion {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parts = s.trim().split('.').map(|part| part.parse::<u32>());
+    let mut next = || {
+      parts
+        .next()
+        .transpose()
+        .map_err(|e| format!("'{}': {}", s, e))
+    };
+
+    let (Some(major), Some(minor)) = (next()?, next()?) else {
+      return Err(format!("'{}' is not a version like 3.1.0", s));
+    };
+    let release = next()?.unwrap_or(0);
+
+    Ok(GameVersion {
+      major,
+      minor,
+      release,
+    })
+  }
+}
+
+impl<'de> Deserialize<'de> for GameVersion {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(D::Error::custom)
+  }
+}
+
+impl fmt::Display for GameVersion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.major, self.minor, self.release)
+  }
+}
+
+#[tauri::command]
+pub fn get_game_version() -> Result<GameVersion, CultivationError> {
+  detect_game_version(&game_exe()?)
+}
+
+pub fn game_exe() -> Result<PathBuf, CultivationError> {
+  config::get_config()
+    .game_install_path
+    .filter(|path| !path.is_empty())
+    .map(PathBuf::from)
+    .ok_or_else(|| CultivationError::Config("game_install_path is not set".to_string()))
+}
+
+/**
+ * Reads the version of the game at `game_exe` (eg. .../GenshinImpact.exe), from the data folder
+ * next to it, or from the executable's version info if that doesn't work out.
+ */
+pub fn detect_game_version(game_exe: &Path) -> Result<GameVersion, CultivationError> {
+  let asb_error = match version_from_asb_settings(game_exe) {
+    Ok(version) => return Ok(version),
+    Err(e) => e,
+  };
+  debug!("{}, trying the executable's version info", asb_error);
+
+  version_from_executable(game_exe).map_err(|exe_error| {
+    CultivationError::GameVersion(format!(
+      "Could not determine the version of {:?} (asb_settings.json: {}; executable: {})",
+      game_exe, asb_error, exe_error
+    ))
+  })
+}
+
+// The game keeps it in <exe name>_Data/StreamingAssets/asb_settings.json as "variance": "3.1_5_..."
+fn version_from_asb_settings(game_exe: &Path) -> Result<GameVersion, String> {
+  let settings_path = asb_settings_path(game_exe);
+  let raw = fs::read_to_string(&settings_path).map_err(|e| e.to_string())?;
+  let settings: Value = serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+
+  let variance = settings["variance"]
+    .as_str()
+    .ok_or_else(|| "there is no \"variance\" in it".to_string())?;
+
+  parse_variance(variance).ok_or_else(|| format!("'{}' is not a version", variance))
+}
+
+fn version_from_executable(game_exe: &Path) -> Result<GameVersion, String> {
+  let map = pelite::FileMap::open(game_exe).map_err(|e| e.to_string())?;
+  let file = pelite::PeFile::from_bytes(&map).map_err(|e| e.to_string())?;
+  let version_info = file
+    .resources()
+    .map_err(|e| e.to_string())?
+    .version_info()
+    .map_err(|e| e.to_string())?;
+
+  let product_version = version_info
+    .fixed()
+    .ok_or_else(|| "there is no fixed version info".to_string())?
+    .dwProductVersion;
+  if product_version.Major == 0 && product_version.Minor == 0 {
+    return Err("the product version is 0.0".to_string());
+  }
+
+  Ok(GameVersion {
+    major: product_version.Major.into(),
+    minor: product_version.Minor.into(),
+    release: product_version.Patch.into(),
+  })
+}
+
+fn asb_settings_path(game_exe: &Path) -> PathBuf {
+  games::data_folder(game_exe)
+    .join("StreamingAssets")
+    .join("asb_settings.json")
+}
+
+// "3.1_5_..." -> 3.1.5
+fn parse_variance(variance: &str) -> Option<GameVersion> {
+  let (major, rest) = variance.split_once('.')?;
+  let mut rest = rest.split('_');
+
+  Some(GameVersion {
+    major: major.parse().ok()?,
+    minor: rest.next()?.parse().ok()?,
+    release: rest
+      .next()
+      .and_then(|release| release.parse().ok())
+      .unwrap_or(0),
+  })
+}
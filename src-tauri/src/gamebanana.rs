@@ -1,3 +1,4 @@
+use crate::error::CultivationError;
 use crate::file_helpers;
 use crate::web;
 use std::collections::HashMap;
@@ -8,12 +9,16 @@ use std::path::PathBuf;
 static SITE_URL: &str = "https://gamebanana.com";
 
 #[tauri::command]
-pub async fn get_download_links(mod_id: String) -> String {
+pub async fn get_download_links(mod_id: String) -> Result<String, CultivationError> {
   web::query(format!("{}/apiv9/Mod/{}/DownloadPage", SITE_URL, mod_id).as_str()).await
 }
 
 #[tauri::command]
-pub async fn list_submissions(mode: String, page: String, search: String) -> String {
+pub async fn list_submissions(
+  mode: String,
+  page: String,
+  search: String,
+) -> Result<String, CultivationError> {
   if search.is_empty() {
     web::query(
       format!(
@@ -36,7 +41,7 @@ pub async fn list_submissions(mode: String, page: String, search: String) -> Str
 }
 
 #[tauri::command]
-pub async fn list_mods(path: String) -> HashMap<String, String> {
+pub async fn list_mods(path: String) -> Result<HashMap<String, String>, CultivationError> {
   let mut path_buf = PathBuf::from(path);
 
   // If the path includes a file, remove it
@@ -47,16 +52,16 @@ pub async fn list_mods(path: String) -> HashMap<String, String> {
   // Ensure we are in the Mods folder
   path_buf.push("Mods");
 
-  // Check if dir is empty
-  if file_helpers::dir_is_empty(path_buf.to_str().unwrap()) {
-    return HashMap::new();
+  // Check if dir is missing or empty
+  if !path_buf.exists() || file_helpers::dir_is_empty(path_buf.to_str().unwrap())? {
+    return Ok(HashMap::new());
   }
 
   let mut mod_info_files = vec![];
   let mut mod_info_strings = HashMap::new();
 
-  for entry in read_dir(path_buf).unwrap() {
-    let entry = entry.unwrap();
+  for entry in read_dir(path_buf)? {
+    let entry = entry?;
     let path = entry.path();
 
     // Check each dir for a modinfo.json file
@@ -72,7 +77,7 @@ pub async fn list_mods(path: String) -> HashMap<String, String> {
           path.to_str().unwrap().to_string(),
           format!(
             "{{ \"name\": \"{}\" }}",
-            path.file_name().unwrap().to_str().unwrap()
+            path.file_name().unwrap_or_default().to_string_lossy()
           ),
         );
       }
@@ -83,13 +88,12 @@ pub async fn list_mods(path: String) -> HashMap<String, String> {
   for mod_info_file in mod_info_files {
     let mut mod_info_string = String::new();
 
-    // It is safe to unwrap here since we *know* that the file exists
-    let mut file = std::fs::File::open(&mod_info_file).unwrap();
-    file.read_to_string(&mut mod_info_string).unwrap();
+    let mut file = std::fs::File::open(&mod_info_file)?;
+    file.read_to_string(&mut mod_info_string)?;
 
     // Push into hashmap using path as key
     mod_info_strings.insert(mod_info_file, mod_info_string);
   }
 
-  mod_info_strings
+  Ok(mod_info_strings)
 }
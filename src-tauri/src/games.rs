@@ -0,0 +1,314 @@
+use crate::config;
+use crate::error::CultivationError;
+
+use serde::Serialize;
+use serde_json::{json, Map};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+// Where Genshin's SDK caches the login, per region
+const LOGIN_VALUE_OVERSEA: &str = "MIHOYOSDK_ADL_PROD_OVERSEA_h1158948810";
+const LOGIN_VALUE_CN: &str = "MIHOYOSDK_ADL_PROD_CN_h3123967166";
+
+// Every game logs in through these
+const ACCOUNT_DOMAINS: [&str; 2] = ["hoyoverse.com", "mihoyo.com"];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AaglModule {
+  Genshin,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Executable {
+  pub name: &'static str,
+  // Under HKEY_CURRENT_USER
+  pub registry_key: &'static str,
+  // None where we don't know which value the SDK uses, the login is left alone then
+  pub login_value: Option<&'static str>,
+}
+
+/**
+ * What launching, patching and redirecting need to know about one of the games.
+ */
+#[derive(Serialize, Debug)]
+pub struct Game {
+  pub id: &'static str,
+  pub name: &'static str,
+  // One per region, global first
+  pub executables: &'static [Executable],
+  // Unity keeps the game data next to the executable, in a folder named after it with this suffix
+  pub data_folder_suffix: &'static str,
+  // In the patch folder, None if there are no patches for the game
+  pub patch_manifest: Option<&'static str>,
+  // Redirected besides the account domains
+  pub redirect_domains: &'static [&'static str],
+  // The other launcher's module that knows how to run the game on Linux, the rest go to Wine as is
+  pub aagl_module: Option<AaglModule>,
+}
+
+pub static GENSHIN: Game = Game {
+  id: "genshin",
+  name: "Genshin Impact",
+  executables: &[
+    Executable {
+      name: "GenshinImpact.exe",
+      registry_key: r"Software\miHoYo\Genshin Impact",
+      login_value: Some(LOGIN_VALUE_OVERSEA),
+    },
+    Executable {
+      name: "YuanShen.exe",
+      registry_key: r"Software\miHoYo\原神",
+      login_value: Some(LOGIN_VALUE_CN),
+    },
+  ],
+  data_folder_suffix: "_Data",
+  patch_manifest: Some("manifest.json"),
+  redirect_domains: &["yuanshen.com"],
+  aagl_module: Some(AaglModule::Genshin),
+};
+
+pub static STAR_RAIL: Game = Game {
+  id: "star_rail",
+  name: "Honkai: Star Rail",
+  executables: &[Executable {
+    name: "StarRail.exe",
+    registry_key: r"Software\Cognosphere\Star Rail",
+    login_value: None,
+  }],
+  data_folder_suffix: "_Data",
+  patch_manifest: None,
+  redirect_domains: &["starrails.com", "bhsr.com"],
+  aagl_module: None,
+};
+
+pub static HONKAI_3RD: Game = Game {
+  id: "honkai_3rd",
+  name: "Honkai Impact 3rd",
+  executables: &[Executable {
+    name: "BH3.exe",
+    registry_key: r"Software\miHoYo\Honkai Impact 3rd",
+    login_value: None,
+  }],
+  data_folder_suffix: "_Data",
+  patch_manifest: None,
+  redirect_domains: &["bh3.com", "honkaiimpact3.com"],
+  aagl_module: None,
+};
+
+pub static ZENLESS_ZONE_ZERO: Game = Game {
+  id: "zenless_zone_zero",
+  name: "Zenless Zone Zero",
+  executables: &[Executable {
+    name: "ZenlessZoneZero.exe",
+    registry_key: r"Software\miHoYo\ZenlessZoneZero",
+    login_value: None,
+  }],
+  data_folder_suffix: "_Data",
+  patch_manifest: None,
+  redirect_domains: &["zenlesszonezero.com"],
+  aagl_module: None,
+};
+
+pub static GAMES: [&Game; 4] = [&GENSHIN, &STAR_RAIL, &HONKAI_3RD, &ZENLESS_ZONE_ZERO];
+
+impl Game {
+  // The region `game_exe` belongs to, or the global one
+  pub fn executable(&self, game_exe: &Path) -> &'static Executable {
+    let name = game_exe.file_name().unwrap_or_default().to_string_lossy();
+
+    self
+      .executables
+      .iter()
+      .find(|exe| exe.name.eq_ignore_ascii_case(&name))
+      .unwrap_or(&self.executables[0])
+  }
+
+  pub fn data_folder(&self, game_exe: &Path) -> PathBuf {
+    let stem = game_exe.file_stem().unwrap_or_default().to_string_lossy();
+    game_exe.with_file_name(format!("{}{}", stem, self.data_folder_suffix))
+  }
+}
+
+// Every executable of every game, eg. for looking for installs
+pub fn executable_names() -> Vec<&'static str> {
+  GAMES
+    .iter()
+    .flat_map(|game| game.executables.iter().map(|exe| exe.name))
+    .collect()
+}
+
+pub fn for_exe(game_exe: &Path) -> Option<&'static Game> {
+  let name = game_exe.file_name()?.to_string_lossy();
+
+  GAMES.iter().copied().find(|game| {
+    game
+      .executables
+      .iter()
+      .any(|exe| exe.name.eq_ignore_ascii_case(&name))
+  })
+}
+
+// The game at game_install_path. Executables we don't recognise are treated as Genshin, like before.
+pub fn current() -> &'static Game {
+  config::shared_config()
+    .game_install_path
+    .as_deref()
+    .and_then(|path| for_exe(Path::new(path)))
+    .unwrap_or(&GENSHIN)
+}
+
+pub fn data_folder(game_exe: &Path) -> PathBuf {
+  for_exe(game_exe).unwrap_or(&GENSHIN).data_folder(game_exe)
+}
+
+// The current game's domains, or every game's with `more`
+pub fn redirect_domains(more: bool) -> Vec<&'static str> {
+  let games: Vec<&Game> = if more {
+    GAMES.to_vec()
+  } else {
+    vec![current()]
+  };
+
+  let mut domains = ACCOUNT_DOMAINS.to_vec();
+  domains.extend(games.iter().flat_map(|game| game.redirect_domains));
+  domains
+}
+
+#[derive(Serialize)]
+pub struct GameEntry {
+  // What it's registered as, see register_game
+  pub name: String,
+  pub game: &'static Game,
+  pub path: String,
+  // Whether it's the one at game_install_path
+  pub active: bool,
+}
+
+/**
+ * The registered games, along with the one at game_install_path if it isn't registered.
+ */
+#[tauri::command]
+pub fn list_games() -> Result<Vec<GameEntry>, CultivationError> {
+  let config = config::load_config()?;
+  let install_path = config.game_install_path.filter(|path| !path.is_empty());
+  let mut registered = config.games.unwrap_or_default();
+
+  if let Some(path) = &install_path {
+    if !registered.values().any(|registered| registered == path) {
+      registered.insert(unused_name(&registered, current()), path.clone());
+    }
+  }
+
+  Ok(
+    registered
+      .into_iter()
+      .map(|(name, path)| GameEntry {
+        name,
+        game: for_exe(Path::new(&path)).unwrap_or(&GENSHIN),
+        active: install_path.as_ref() == Some(&path),
+        path,
+      })
+      .collect(),
+  )
+}
+
+/**
+ * Remembers an install of one of the games as `name`, so it can be switched to with select_game.
+ * Any number of installs can be registered, eg. a global and a Chinese one.
+ */
+#[tauri::command]
+pub fn register_game(name: String, path: String) -> Result<(), CultivationError> {
+  let name = name.trim().to_string();
+  if name.is_empty() {
+    return Err(CultivationError::Config(
+      "A game needs a name to be registered".to_string(),
+    ));
+  }
+  let game = require_game(&path)?;
+
+  // From the file, so --set and --profile don't end up in it
+  let mut games = config::load_file_config()?.games.unwrap_or_default();
+  games.insert(name.clone(), path.clone());
+
+  let mut changes = Map::new();
+  changes.insert("games".to_string(), json!(games));
+  config::update_config(changes)?;
+
+  info!("Registered {} at {} as {}", game.name, path, name);
+  Ok(())
+}
+
+/**
+ * Makes the game registered as `name` the one that is launched, patched and redirected.
+ */
+#[tauri::command]
+pub fn select_game(name: String) -> Result<(), CultivationError> {
+  let config = config::load_file_config()?;
+  let mut games = config.games.unwrap_or_default();
+
+  let path = games.get(&name).cloned().ok_or_else(|| {
+    CultivationError::Config(format!("There is no game registered as '{}'", name))
+  })?;
+
+  // Keep the one being switched away from, so it can be switched back to
+  if let Some(current) = config.game_install_path.filter(|path| !path.is_empty()) {
+    if let Some(current_game) = for_exe(Path::new(&current)) {
+      if !games.values().any(|registered| registered == &current) {
+        games.insert(unused_name(&games, current_game), current);
+      }
+    }
+  }
+
+  let mut changes = Map::new();
+  changes.insert("game_install_path".to_string(), json!(path));
+  changes.insert("games".to_string(), json!(games));
+  config::update_config(changes)?;
+
+  info!("Switched to {}", name);
+  Ok(())
+}
+
+/**
+ * Like select_game, but only until Cultivation exits. configuration.json is left as it is.
+ */
+pub fn use_game(name: String) -> Result<(), CultivationError> {
+  let path = config::load_config()?
+    .games
+    .unwrap_or_default()
+    .remove(&name)
+    .ok_or_else(|| {
+      CultivationError::Config(format!("There is no game registered as '{}'", name))
+    })?;
+
+  // Added to a profile's overrides rather than replacing them
+  let mut changes = Map::new();
+  changes.insert("game_install_path".to_string(), json!(path));
+  config::add_session_overrides(changes);
+
+  info!("Using {} for this session", name);
+  Ok(())
+}
+
+// The game `path` is an executable of, or an error listing the ones we know
+pub fn require_game(path: &str) -> Result<&'static Game, CultivationError> {
+  for_exe(Path::new(path)).ok_or_else(|| {
+    CultivationError::Config(format!(
+      "{} is not a game Cultivation knows, expected one of {}",
+      path,
+      executable_names().join(", ")
+    ))
+  })
+}
+
+// The game's id, numbered if another install already has it
+fn unused_name(games: &BTreeMap<String, String>, game: &Game) -> String {
+  (1..)
+    .map(|i| match i {
+      1 => game.id.to_string(),
+      _ => format!("{}_{}", game.id, i),
+    })
+    .find(|name| !games.contains_key(name))
+    .unwrap()
+}
@@ -0,0 +1,339 @@
+use crate::config;
+use crate::error::CultivationError;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+// Hooks without a timeout are killed after this long
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+  // Before patching and starting the proxy
+  PreLaunch,
+  PostLaunch,
+  // After game_closed
+  PostExit,
+}
+
+impl fmt::Display for HookStage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      HookStage::PreLaunch => write!(f, "pre_launch"),
+      HookStage::PostLaunch => write!(f, "post_launch"),
+      HookStage::PostExit => write!(f, "post_exit"),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+  // Stop the launch, and the stage's remaining hooks
+  Abort,
+  #[default]
+  Warn,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hook {
+  pub command: String,
+  #[serde(default)]
+  pub args: Vec<String>,
+  #[serde(default)]
+  pub env: BTreeMap<String, String>,
+  pub working_dir: Option<String>,
+  // In seconds
+  pub timeout: Option<u64>,
+  #[serde(default)]
+  pub on_failure: FailurePolicy,
+  // Left running, eg. a packet capture for the whole session. The timeout doesn't apply,
+  // only failing to start counts as failing.
+  #[serde(default)]
+  pub detach: bool,
+}
+
+// Commands to run around a game session, eg. starting a packet capture or backing up a database
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Hooks {
+  #[serde(default)]
+  pub pre_launch: Vec<Hook>,
+  #[serde(default)]
+  pub post_launch: Vec<Hook>,
+  #[serde(default)]
+  pub post_exit: Vec<Hook>,
+}
+
+impl Hooks {
+  fn for_stage(&self, stage: HookStage) -> &[Hook] {
+    match stage {
+      HookStage::PreLaunch => &self.pre_launch,
+      HookStage::PostLaunch => &self.post_launch,
+      HookStage::PostExit => &self.post_exit,
+    }
+  }
+}
+
+/**
+ * Runs the hooks configured for `stage` one after another, with their output going to the log.
+ * A failing hook set to abort stops the rest with a hook error, and the launch is called off.
+ * Any other failure is only logged.
+ */
+pub fn run(stage: HookStage) -> Result<(), CultivationError> {
+  let hooks = config::get_config().hooks.unwrap_or_default();
+  run_all(hooks.for_stage(stage), stage)
+}
+
+// Whether there's anything to run for `stage`
+pub fn has_hooks(stage: HookStage) -> bool {
+  config::get_config()
+    .hooks
+    .map_or(false, |hooks| !hooks.for_stage(stage).is_empty())
+}
+
+fn run_all(hooks: &[Hook], stage: HookStage) -> Result<(), CultivationError> {
+  for hook in hooks {
+    let Err(e) = run_hook(hook, stage) else {
+      continue;
+    };
+
+    match hook.on_failure {
+      FailurePolicy::Abort => {
+        return Err(CultivationError::Hook(format!(
+          "The {} hook `{}` failed: {}",
+          stage, hook.command, e
+        )));
+      }
+      FailurePolicy::Warn => warn!(
+        "The {} hook `{}` failed, carrying on: {}",
+        stage, hook.command, e
+      ),
+    }
+  }
+
+  Ok(())
+}
+
+// For when there's nothing left to abort, like after the game closed
+pub fn run_or_log(stage: HookStage) {
+  if let Err(e) = run(stage) {
+    error!("{}", e);
+  }
+}
+
+#[tauri::command]
+pub async fn run_hooks(stage: HookStage) -> Result<(), CultivationError> {
+  tauri::async_runtime::spawn_blocking(move || run(stage))
+    .await
+    .map_err(|e| {
+      CultivationError::Io(format!(
+        "Running the {} hooks was interrupted: {}",
+        stage, e
+      ))
+    })?
+}
+
+fn run_hook(hook: &Hook, stage: HookStage) -> Result<(), String> {
+  let mut cmd = Command::new(&hook.command);
+  cmd
+    .args(&hook.args)
+    .env("CULTIVATION_HOOK", stage.to_string())
+    .env(
+      "CULTIVATION_GAME_PATH",
+      config::get_config().game_install_path.unwrap_or_default(),
+    )
+    .envs(&hook.env)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+  if let Some(dir) = &hook.working_dir {
+    cmd.current_dir(dir);
+  }
+
+  info!(
+    "Running {} hook: {} {}",
+    stage,
+    hook.command,
+    hook.args.join(" ")
+  );
+  let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+
+  // Not waited for, whatever the hook leaves running in the background keeps the pipes open
+  log_output(child.stdout.take(), &hook.command, false);
+  log_output(child.stderr.take(), &hook.command, true);
+
+  if hook.detach {
+    let command = hook.command.clone();
+    // Still waited for, so it doesn't linger as a zombie once it exits
+    thread::spawn(move || match child.wait() {
+      Ok(status) => info!(
+        "The detached {} hook `{}` exited with {}",
+        stage, command, status
+      ),
+      Err(e) => warn!(
+        "Lost track of the detached {} hook `{}`: {}",
+        stage, command, e
+      ),
+    });
+
+    info!("Left the {} hook `{}` running", stage, hook.command);
+    return Ok(());
+  }
+
+  let timeout = hook
+    .timeout
+    .map(Duration::from_secs)
+    .unwrap_or(DEFAULT_TIMEOUT);
+  let deadline = Instant::now() + timeout;
+
+  let status = loop {
+    if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+      break status;
+    }
+
+    if Instant::now() >= deadline {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(format!(
+        "it was killed after running for {}s",
+        timeout.as_secs()
+      ));
+    }
+    thread::sleep(POLL_INTERVAL);
+  };
+
+  if !status.success() {
+    return Err(format!("it exited with {}", status));
+  }
+
+  info!("The {} hook `{}` finished", stage, hook.command);
+  Ok(())
+}
+
+fn log_output<R: Read + Send + 'static>(pipe: Option<R>, command: &str, is_stderr: bool) {
+  let Some(pipe) = pipe else {
+    return;
+  };
+  let command = command.to_string();
+
+  thread::spawn(move || {
+    for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+      if is_stderr {
+        warn!("[{}] {}", command, line);
+      } else {
+        info!("[{}] {}", command, line);
+      }
+    }
+  });
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+  use std::fs;
+  use std::path::{Path, PathBuf};
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+      std::env::temp_dir().join(format!("cultivation-hooks-{}-{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn hook(script: &str, on_failure: FailurePolicy) -> Hook {
+    Hook {
+      command: "sh".to_string(),
+      args: vec!["-c".to_string(), script.to_string()],
+      env: BTreeMap::new(),
+      working_dir: None,
+      timeout: None,
+      on_failure,
+      detach: false,
+    }
+  }
+
+  fn touch(path: &Path) -> String {
+    format!("touch '{}'", path.display())
+  }
+
+  #[test]
+  fn carries_on_after_a_hook_that_warns() {
+    let dir = temp_dir("warn");
+    let ran = dir.join("ran");
+    let hooks = [
+      hook("exit 3", FailurePolicy::Warn),
+      hook("no-such-command-cultivation", FailurePolicy::Warn),
+      hook(&touch(&ran), FailurePolicy::Abort),
+    ];
+
+    run_all(&hooks, HookStage::PreLaunch).unwrap();
+    assert!(ran.exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn stops_at_a_hook_that_aborts() {
+    let dir = temp_dir("abort");
+    let first = dir.join("first");
+    let skipped = dir.join("skipped");
+    let hooks = [
+      hook(&touch(&first), FailurePolicy::Abort),
+      hook("exit 1", FailurePolicy::Abort),
+      hook(&touch(&skipped), FailurePolicy::Warn),
+    ];
+
+    let result = run_all(&hooks, HookStage::PreLaunch);
+    assert!(matches!(result, Err(CultivationError::Hook(_))));
+    assert!(first.exists());
+    assert!(!skipped.exists());
+
+    // Failing to start at all counts too
+    let mut missing = hook("", FailurePolicy::Abort);
+    missing.command = "no-such-command-cultivation".to_string();
+    assert!(run_all(&[missing], HookStage::PreLaunch).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn kills_hooks_that_run_too_long() {
+    let mut slow = hook("sleep 5", FailurePolicy::Abort);
+    slow.timeout = Some(0);
+
+    let started = Instant::now();
+    let result = run_all(&[slow], HookStage::PostExit);
+    assert!(matches!(result, Err(CultivationError::Hook(message)) if message.contains("killed")));
+    assert!(started.elapsed() < Duration::from_secs(5));
+  }
+
+  #[test]
+  fn leaves_detached_hooks_running() {
+    let dir = temp_dir("detach");
+    let done = dir.join("done");
+    let mut background = hook(&format!("sleep 1; {}", touch(&done)), FailurePolicy::Abort);
+    background.detach = true;
+    background.timeout = Some(0);
+
+    run_all(&[background], HookStage::PostLaunch).unwrap();
+    assert!(!done.exists());
+
+    // Not killed by the timeout
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !done.exists() && Instant::now() < deadline {
+      thread::sleep(POLL_INTERVAL);
+    }
+    assert!(done.exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,5 +1,7 @@
+use crate::error::CultivationError;
 use crate::system_helpers::*;
 use std::path::{Path, PathBuf};
+use tracing::warn;
 
 #[tauri::command]
 pub async fn get_lang(window: tauri::Window, lang: String) -> String {
@@ -19,26 +21,26 @@ pub async fn get_lang(window: tauri::Window, lang: String) -> String {
 }
 
 #[tauri::command]
-pub async fn get_languages() -> std::collections::HashMap<String, String> {
+pub async fn get_languages() -> Result<std::collections::HashMap<String, String>, CultivationError>
+{
   // for each lang file, set the key as the filename and the value as the lang_name contained in the file
   let mut languages = std::collections::HashMap::new();
 
-  let lang_files = std::fs::read_dir(Path::new(&install_location()).join("lang")).unwrap();
+  let lang_files = std::fs::read_dir(Path::new(&install_location()).join("lang"))?;
 
   for entry in lang_files {
-    let entry = entry.unwrap();
+    let entry = entry?;
     let path = entry.path();
     let filename = path
       .file_name()
-      .unwrap_or_else(|| panic!("Failed to get filename from path: {:?}", path))
-      .to_str()
-      .unwrap_or_else(|| panic!("Failed to convert filename to string: {:?}", path))
+      .unwrap_or_default()
+      .to_string_lossy()
       .to_string();
 
     let content = match std::fs::read_to_string(&path) {
       Ok(x) => x,
       Err(e) => {
-        println!("Failed to read language file: {}", e);
+        warn!("Failed to read language file: {}", e);
         break;
       }
     };
@@ -46,7 +48,7 @@ pub async fn get_languages() -> std::collections::HashMap<String, String> {
     languages.insert(filename.to_string(), content);
   }
 
-  languages
+  Ok(languages)
 }
 
 pub fn emit_lang_err(window: tauri::Window, msg: String) {
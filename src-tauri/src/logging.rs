@@ -0,0 +1,378 @@
+use crate::config;
+use crate::error::CultivationError;
+use crate::paths;
+
+use once_cell::sync::OnceCell;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+  fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+// Log files are rotated daily and the oldest ones are removed once they add up to more than this
+const MAX_LOG_BYTES: u64 = 20 * 1024 * 1024;
+// A day's log is continued in a new file past this, so it can't grow without limit either
+const MAX_LOG_FILE_BYTES: u64 = 5 * 1024 * 1024;
+const LOG_FILE_PREFIX: &str = "cultivation.log";
+
+// Swapped by apply_config, the level isn't final until the --set overrides are in
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+pub fn logs_dir() -> PathBuf {
+  paths::data_dir().join("logs")
+}
+
+/**
+ * Sets up logging to stdout and to a daily rotated file in the logs directory.
+ * The returned guard flushes the file writer when dropped, so keep it alive until exit.
+ */
+pub fn init() -> Option<WorkerGuard> {
+  // The config is only read once the subscriber is in, so its warnings end up in the log
+  let (filter_layer, filter_handle) = reload::Layer::new(filter(false));
+  let _ = FILTER.set(filter_handle);
+
+  let stdout_layer = fmt::layer().with_target(true);
+
+  let dir = logs_dir();
+  let (file_layer, guard) = match fs::create_dir_all(&dir) {
+    Ok(_) => {
+      prune_logs(&dir, MAX_LOG_BYTES);
+
+      let appender = LogAppender::new(dir, MAX_LOG_BYTES, MAX_LOG_FILE_BYTES);
+      let (writer, guard) = tracing_appender::non_blocking(appender);
+
+      (
+        Some(fmt::layer().with_ansi(false).with_writer(writer)),
+        Some(guard),
+      )
+    }
+    Err(e) => {
+      eprintln!("Failed to create log directory {:?}: {}", dir, e);
+      (None, None)
+    }
+  };
+
+  tracing_subscriber::registry()
+    .with(filter_layer)
+    .with(stdout_layer)
+    .with(file_layer)
+    .init();
+  apply_config();
+
+  guard
+}
+
+// Picks up debug_enabled again, after the --set overrides and whenever the configuration changes
+pub fn apply_config() {
+  let Some(handle) = FILTER.get() else {
+    return;
+  };
+
+  if let Err(e) = handle.reload(filter(debug_enabled())) {
+    eprintln!("Failed to change the log level: {}", e);
+  }
+}
+
+fn debug_enabled() -> bool {
+  config::shared_config().debug_enabled.unwrap_or(false)
+}
+
+// Debug output is only ours, the dependencies are far too chatty at that level
+fn filter(debug: bool) -> EnvFilter {
+  EnvFilter::new(if debug {
+    "info,cultivation=debug"
+  } else {
+    "info"
+  })
+}
+
+/**
+ * Writes to cultivation.log.<date>, like a daily rolling appender, but also moves on to
+ * cultivation.log.<date>.1, .2, ... once a file reaches `max_file_bytes`.
+ * The logs are pruned down to `max_bytes` whenever a new file is started.
+ */
+struct LogAppender {
+  dir: PathBuf,
+  max_bytes: u64,
+  max_file_bytes: u64,
+  day: u64,
+  part: u64,
+  file: Option<File>,
+  written: u64,
+}
+
+impl LogAppender {
+  fn new(dir: PathBuf, max_bytes: u64, max_file_bytes: u64) -> Self {
+    let day = utc_day();
+
+    // Carry on with today's last file, like the rolling appender used to
+    let part = log_files(&dir)
+      .iter()
+      .filter_map(|path| log_file_key(path))
+      .filter(|(date, _)| *date == format_day(day))
+      .map(|(_, part)| part)
+      .max()
+      .unwrap_or(0);
+
+    LogAppender {
+      dir,
+      max_bytes,
+      max_file_bytes,
+      day,
+      part,
+      file: None,
+      written: 0,
+    }
+  }
+
+  fn path(&self) -> PathBuf {
+    let name = match self.part {
+      0 => format!("{}.{}", LOG_FILE_PREFIX, format_day(self.day)),
+      part => format!("{}.{}.{}", LOG_FILE_PREFIX, format_day(self.day), part),
+    };
+    self.dir.join(name)
+  }
+
+  fn open(&mut self) -> io::Result<&mut File> {
+    if self.file.is_none() {
+      let path = self.path();
+      let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+      self.written = file.metadata().map_or(0, |meta| meta.len());
+      self.file = Some(file);
+    }
+
+    Ok(self.file.as_mut().unwrap())
+  }
+
+  fn start_file(&mut self, day: u64, part: u64) {
+    self.day = day;
+    self.part = part;
+    self.file = None;
+  }
+}
+
+impl Write for LogAppender {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let day = utc_day();
+    let rolled = if day != self.day {
+      self.start_file(day, 0);
+      true
+    } else {
+      self.open()?;
+      // A single line bigger than the cap still goes in, on its own
+      if self.written > 0 && self.written + buf.len() as u64 > self.max_file_bytes {
+        self.start_file(day, self.part + 1);
+        true
+      } else {
+        false
+      }
+    };
+
+    let written = self.open()?.write(buf)?;
+    self.written += written as u64;
+
+    // Once the new file is there, so it's the newest and is kept
+    if rolled {
+      prune_logs(&self.dir, self.max_bytes);
+    }
+
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match &mut self.file {
+      Some(file) => file.flush(),
+      None => Ok(()),
+    }
+  }
+}
+
+// A new log file is started at midnight UTC
+fn utc_day() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |since| since.as_secs() / (24 * 60 * 60))
+}
+
+// Days since 1970-01-01 as YYYY-MM-DD, see http://howardhinnant.github.io/date_algorithms.html
+fn format_day(day: u64) -> String {
+  let z = day as i64 + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let d = doy - (153 * mp + 2) / 5 + 1;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 };
+  let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+  format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+// The date and part of a log file, None for cultivation.log itself or anything else
+fn log_file_key(path: &Path) -> Option<(String, u64)> {
+  let name = path.file_name()?.to_str()?;
+  let rest = name.strip_prefix(LOG_FILE_PREFIX)?.strip_prefix('.')?;
+
+  match rest.split_once('.') {
+    Some((date, part)) => Some((date.to_string(), part.parse().ok()?)),
+    None => Some((rest.to_string(), 0)),
+  }
+}
+
+// Log files sorted oldest first, by date and then by part
+pub fn log_files(dir: &Path) -> Vec<PathBuf> {
+  let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+    Ok(entries) => entries
+      .flatten()
+      .map(|entry| entry.path())
+      .filter(|path| {
+        path.is_file()
+          && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with(LOG_FILE_PREFIX))
+      })
+      .collect(),
+    Err(_) => vec![],
+  };
+
+  files.sort_by_key(|path| (log_file_key(path), path.clone()));
+  files
+}
+
+fn prune_logs(dir: &Path, max_bytes: u64) {
+  let files = log_files(dir);
+  let mut total: u64 = files
+    .iter()
+    .filter_map(|path| fs::metadata(path).ok())
+    .map(|meta| meta.len())
+    .sum();
+
+  // Never remove the newest file, it's about to be written to
+  for path in files.iter().take(files.len().saturating_sub(1)) {
+    if total <= max_bytes {
+      break;
+    }
+
+    let len = fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+    if fs::remove_file(path).is_ok() {
+      total -= len;
+    }
+  }
+}
+
+#[tauri::command]
+pub fn get_logs(lines: Option<usize>) -> Result<String, CultivationError> {
+  let lines = lines.unwrap_or(500);
+  let files = log_files(&logs_dir());
+
+  // Walk backwards through the files until we have enough lines
+  let mut collected: Vec<String> = vec![];
+  for path in files.iter().rev() {
+    let contents = fs::read_to_string(path)?;
+    let mut chunk: Vec<String> = contents.lines().map(|line| line.to_string()).collect();
+
+    chunk.append(&mut collected);
+    collected = chunk;
+
+    if collected.len() >= lines {
+      break;
+    }
+  }
+
+  let skip = collected.len().saturating_sub(lines);
+  Ok(collected[skip..].join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn logs_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cultivation-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn names(dir: &Path) -> Vec<String> {
+    log_files(dir)
+      .iter()
+      .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+      .collect()
+  }
+
+  #[test]
+  fn formats_days_as_dates() {
+    assert_eq!(format_day(0), "1970-01-01");
+    assert_eq!(format_day(11_017), "2000-03-01");
+    assert_eq!(format_day(19_782), "2024-02-29");
+  }
+
+  #[test]
+  fn sorts_parts_after_their_day() {
+    let dir = logs_dir("log-order");
+    for name in [
+      "cultivation.log.2024-01-02",
+      "cultivation.log.2024-01-01.10",
+      "cultivation.log.2024-01-01.2",
+      "cultivation.log.2024-01-01",
+    ] {
+      fs::write(dir.join(name), "").unwrap();
+    }
+
+    assert_eq!(
+      names(&dir),
+      [
+        "cultivation.log.2024-01-01",
+        "cultivation.log.2024-01-01.2",
+        "cultivation.log.2024-01-01.10",
+        "cultivation.log.2024-01-02",
+      ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn prunes_the_oldest_logs_but_never_the_newest() {
+    let dir = logs_dir("log-prune");
+    fs::write(dir.join("cultivation.log.2024-01-01"), [b'a'; 100]).unwrap();
+    fs::write(dir.join("cultivation.log.2024-01-02"), [b'a'; 100]).unwrap();
+    fs::write(dir.join("cultivation.log.2024-01-03"), [b'a'; 300]).unwrap();
+
+    prune_logs(&dir, 250);
+
+    assert_eq!(names(&dir), ["cultivation.log.2024-01-03"]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn starts_a_new_file_once_one_is_full() {
+    let dir = logs_dir("log-cap");
+    let mut appender = LogAppender::new(dir.clone(), 250, 100);
+
+    for _ in 0..10 {
+      appender.write_all(&[b'a'; 40]).unwrap();
+    }
+    appender.flush().unwrap();
+
+    let files = log_files(&dir);
+    let sizes: Vec<u64> = files
+      .iter()
+      .map(|path| fs::metadata(path).unwrap().len())
+      .collect();
+    assert!(sizes.iter().all(|size| *size <= 100));
+    assert!(sizes.iter().sum::<u64>() <= 250);
+    // The last part written to is kept
+    assert_eq!(files.last(), Some(&appender.path()));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -4,18 +4,21 @@
 )]
 
 use args::{Args, ArgsError};
+use error::CultivationError;
 use file_helpers::dir_exists;
+use hooks::HookStage;
 
 use once_cell::sync::Lazy;
 use proxy::set_proxy_addr;
 use std::fs;
 use std::io::Write;
 use std::{collections::HashMap, sync::Mutex};
-use tauri::api::path::data_dir;
 use tauri::async_runtime::block_on;
+use tauri::Manager;
 
 use std::thread;
 use sysinfo::{Pid, ProcessExt, System, SystemExt};
+use tracing::{error, info, warn};
 
 #[cfg(target_os = "windows")]
 use crate::admin::reopen_as_admin;
@@ -27,18 +30,36 @@ use std::{
   thread::{sleep, JoinHandle},
   time::{Duration, Instant},
 };
+#[cfg(unix)]
+use tracing::debug;
 
 mod admin;
 mod config;
+mod diagnostics;
+mod discovery;
 mod downloader;
+mod error;
 mod file_helpers;
+mod game_version;
 mod gamebanana;
+mod games;
+mod hooks;
 mod lang;
+mod logging;
+mod metadata;
 mod patch;
+mod patch_journal;
+mod patch_manifest;
+mod paths;
+mod profiles;
 mod proxy;
 mod release;
+mod rsa_key;
+mod settings;
+mod snapshots;
 mod system_helpers;
 mod unzip;
+mod verify;
 mod web;
 
 static WATCH_GAME_PROCESS: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(String::new()));
@@ -48,6 +69,10 @@ static GC_PID: std::sync::Mutex<usize> = Mutex::new(696969);
 #[cfg(target_os = "linux")]
 pub static AAGL_THREAD: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
 
+// Runs the post_exit hooks for a game launched from the command line, see watch_cli_game
+static CLI_GAME_WATCHER: Lazy<Mutex<Option<thread::JoinHandle<()>>>> =
+  Lazy::new(|| Mutex::new(None));
+
 fn try_flush() {
   std::io::stdout().flush().unwrap_or(())
 }
@@ -77,11 +102,26 @@ async fn parse_args(inp: &Vec<String>) -> Result<Args, ArgsError> {
     "patch",
     "Patch your game before launching, with whatever your game version needs",
   );
+  args.flag(
+    "",
+    "dry-run",
+    "With --patch, only print what patching would change, without changing it or launching the game",
+  );
+  args.flag(
+    "",
+    "swap-foreign-patch",
+    "With --patch, put another launcher's patch aside while playing instead of stopping",
+  );
   args.flag(
     "N",
     "non-elevated-game",
     "Launch the game without admin permissions",
   );
+  args.flag(
+    "D",
+    "export-diagnostics",
+    "Write a diagnostics bundle for bug reports to the data directory",
+  );
   args.option(
     "H",
     "host",
@@ -90,10 +130,58 @@ async fn parse_args(inp: &Vec<String>) -> Result<Args, ArgsError> {
     getopts::Occur::Optional,
     None,
   );
+  args.option(
+    "",
+    "data-dir",
+    "Store configuration, certificates, themes, logs and downloads in this folder",
+    "PATH",
+    getopts::Occur::Optional,
+    None,
+  );
+  args.option(
+    "",
+    "set",
+    "Override a configuration value for this run. Can be repeated.",
+    "KEY=VALUE",
+    getopts::Occur::Multi,
+    None,
+  );
+  args.option(
+    "",
+    "decrypt-metadata",
+    "Decrypt a global-metadata.dat, writing it next to the original with .decrypted added",
+    "PATH",
+    getopts::Occur::Optional,
+    None,
+  );
+  args.option(
+    "",
+    "encrypt-metadata",
+    "Encrypt a global-metadata.dat, writing it next to the original with .encrypted added",
+    "PATH",
+    getopts::Occur::Optional,
+    None,
+  );
+  args.option(
+    "",
+    "profile",
+    "Use a saved server profile for this session, without making it the active one",
+    "PROFILE_NAME",
+    getopts::Occur::Optional,
+    None,
+  );
+  args.option(
+    "",
+    "game",
+    "Use the game registered under this name, for this session only",
+    "NAME",
+    getopts::Occur::Optional,
+    None,
+  );
   args.option(
     "a",
     "game-args",
-    "Arguments to pass to the game process, if launching it",
+    "Arguments to pass to the game process, if launching it. Quoted arguments stay together",
     r#""-opt-one -opt-two""#,
     getopts::Occur::Optional,
     None,
@@ -101,27 +189,102 @@ async fn parse_args(inp: &Vec<String>) -> Result<Args, ArgsError> {
 
   args.parse(inp).unwrap();
 
-  let config = config::get_config();
-
   if args.value_of("help")? {
     println!("{}", args.full_usage());
     std::process::exit(0);
   }
 
-  if args.value_of("launch-game")? {
+  // Command line overrides win over the environment and configuration.json
+  for assignment in args.values_of::<String>("set").unwrap_or_default() {
+    if let Err(e) = config::set_cli_override(&assignment) {
+      error!("Ignoring --set {}: {}", assignment, e);
+    }
+  }
+
+  // Used first, so the rest of the flags see the profile's settings
+  if let Ok(name) = args.value_of::<String>("profile") {
+    if let Err(e) = profiles::use_profile(name) {
+      error!("Failed to activate profile: {}", e);
+    }
+  }
+
+  if let Ok(id) = args.value_of::<String>("game") {
+    if let Err(e) = games::use_game(id) {
+      error!("Failed to switch games: {}", e);
+    }
+  }
+
+  let config = config::get_config();
+
+  if args.value_of("export-diagnostics")? {
+    match diagnostics::export_diagnostics(None, None).await {
+      Ok(path) => println!("Diagnostics written to {}", path),
+      Err(e) => error!("Failed to export diagnostics: {}", e),
+    }
+  }
+
+  if let Ok(path) = args.value_of::<String>("decrypt-metadata") {
+    match metadata::decrypt_metadata(path, None) {
+      Ok(output) => println!("Decrypted metadata written to {}", output),
+      Err(e) => error!("Failed to decrypt the metadata: {}", e),
+    }
+  }
+
+  if let Ok(path) = args.value_of::<String>("encrypt-metadata") {
+    match metadata::encrypt_metadata(path, None) {
+      Ok(output) => println!("Encrypted metadata written to {}", output),
+      Err(e) => error!("Failed to encrypt the metadata: {}", e),
+    }
+  }
+
+  let dry_run = args.value_of("dry-run")?;
+  let swap_foreign = args.value_of("swap-foreign-patch")?;
+  if dry_run && args.value_of("patch")? {
+    match patch::patch_game(Some(true), Some(swap_foreign)).await {
+      Ok(plan) if plan.is_empty() => println!("Patching would not change anything"),
+      Ok(plan) => {
+        println!("Patching would:");
+        for action in plan {
+          println!("  {}", action);
+        }
+      }
+      Err(e) => error!("Failed to plan patching the game: {}", e),
+    }
+  }
+
+  if args.value_of("launch-game")? && !dry_run {
     let game_path = config.game_install_path;
-    let game_args: String = args.value_of("game-args").unwrap_or_default();
+    let game_args: String = args
+      .value_of("game-args")
+      .unwrap_or_else(|_| config.launch_args.clone().unwrap_or_default());
+
+    let pre_launch = hooks::run(HookStage::PreLaunch);
+    if let Err(e) = &pre_launch {
+      error!("Not launching the game: {}", e);
+    }
 
-    // Patch if needed
-    if args.value_of("patch")? {
-      patch::patch_game().await;
+    // Only when asked for, patch_rsa is the launcher's setting and the command line doesn't follow it
+    if pre_launch.is_ok() && args.value_of("patch")? {
+      if let Err(e) = patch::patch_game(None, Some(swap_foreign)).await {
+        error!("Failed to patch the game: {}", e);
+      }
     }
 
-    if game_path.is_some() {
-      if args.value_of("non-elevated-game")? {
-        system_helpers::run_un_elevated(game_path.unwrap(), Some(game_args))
+    if let (true, Some(game_path)) = (pre_launch.is_ok(), game_path) {
+      let launched = if args.value_of("non-elevated-game")? {
+        system_helpers::run_un_elevated(game_path.clone(), Some(game_args))
       } else {
-        system_helpers::run_program(game_path.unwrap(), Some(game_args))
+        system_helpers::run_program(game_path.clone(), Some(game_args))
+      };
+
+      match launched {
+        Ok(_) => {
+          hooks::run_or_log(HookStage::PostLaunch);
+          if hooks::has_hooks(HookStage::PostExit) {
+            watch_cli_game(&game_path);
+          }
+        }
+        Err(e) => error!("Failed to launch the game: {}", e),
       }
     }
   }
@@ -140,7 +303,9 @@ async fn parse_args(inp: &Vec<String>) -> Result<Args, ArgsError> {
     }
     let java_path = config.java_path.unwrap();
 
-    system_helpers::run_jar(server_jar, server_path.to_string(), java_path);
+    if let Err(e) = system_helpers::run_jar(server_jar, server_path.to_string(), java_path) {
+      error!("Failed to launch the server: {}", e);
+    }
   }
 
   if args.value_of::<String>("host").is_ok() && !args.value_of::<String>("host")?.is_empty() {
@@ -149,16 +314,16 @@ async fn parse_args(inp: &Vec<String>) -> Result<Args, ArgsError> {
   }
 
   if args.value_of("proxy")? {
-    println!("Starting proxy server...");
-    let mut pathbuf = tauri::api::path::data_dir().unwrap();
-    pathbuf.push("cultivation");
-    pathbuf.push("ca");
+    info!("Starting proxy server...");
+    let pathbuf = paths::data_dir().join("ca");
 
     if args.value_of("other_redirects")? {
       proxy::set_redirect_more();
     }
 
-    connect(8035, pathbuf.to_str().unwrap().to_string()).await;
+    if let Err(e) = connect(8035, pathbuf.to_str().unwrap().to_string()).await {
+      error!("Failed to start the proxy: {}", e);
+    }
   }
 
   Ok(args)
@@ -166,20 +331,32 @@ async fn parse_args(inp: &Vec<String>) -> Result<Args, ArgsError> {
 
 fn main() -> Result<(), ArgsError> {
   let args: Vec<String> = std::env::args().collect();
+
+  // Everything below reads the data dir, so it has to be settled first
+  if let Some(dir) = paths::data_dir_from_args(&args) {
+    paths::set_data_dir(dir);
+  }
+
+  let _log_guard = logging::init();
+  info!("Using data directory {:?}", paths::data_dir());
+  config::migrate_config_file();
+
+  // Before anything can patch again
+  patch_journal::recover();
+
   let parsed_args = block_on(parse_args(&args)).unwrap();
+  logging::apply_config();
 
   #[cfg(target_os = "windows")]
   if !is_elevated() && !parsed_args.value_of("no-admin")? {
-    println!("===============================================================================");
-    println!("You running as a non-elevated user. Some stuff will almost definitely not work.");
-    println!("===============================================================================");
+    warn!("You running as a non-elevated user. Some stuff will almost definitely not work.");
 
     reopen_as_admin();
   }
 
-  // Setup datadir/cultivation just in case something went funky and it wasn't made
-  if !dir_exists(data_dir().unwrap().join("cultivation").to_str().unwrap()) {
-    fs::create_dir_all(data_dir().unwrap().join("cultivation")).unwrap();
+  // Setup the data dir just in case something went funky and it wasn't made
+  if !dir_exists(paths::data_dir().to_str().unwrap()) {
+    fs::create_dir_all(paths::data_dir()).unwrap();
   }
 
   // Always set CWD to the location of the executable.
@@ -189,14 +366,26 @@ fn main() -> Result<(), ArgsError> {
 
   // For disabled GUI
   ctrlc::set_handler(|| {
-    disconnect();
-    block_on(patch::unpatch_game());
+    disconnect_or_log();
+    unpatch_or_log();
     std::process::exit(0);
   })
   .unwrap_or(());
 
   if !parsed_args.value_of("no-gui")? {
     tauri::Builder::default()
+      .setup(|app| {
+        // The allowlist only covers $DATA/cultivation, a portable or custom data dir has to be added
+        let data_dir = paths::data_dir();
+        app.fs_scope().allow_directory(&data_dir, true)?;
+        app
+          .asset_protocol_scope()
+          .allow_directory(&data_dir, true)?;
+
+        config::watch_config(app.handle());
+
+        Ok(())
+      })
       .invoke_handler(tauri::generate_handler![
         enable_process_watcher,
         enable_grasscutter_watcher,
@@ -227,10 +416,38 @@ fn main() -> Result<(), ArgsError> {
         system_helpers::jvm_remove_cap,
         patch::patch_game,
         patch::unpatch_game,
+        patch::patch_status,
+        metadata::decrypt_metadata,
+        metadata::encrypt_metadata,
+        rsa_key::convert_rsa_key,
+        rsa_key::import_rsa_key,
+        rsa_key::remove_rsa_key,
+        verify::verify_game_files,
+        verify::save_game_manifest,
+        verify::list_game_manifests,
+        snapshots::list_snapshots,
+        snapshots::rollback_snapshot,
+        patch_journal::repair_patch,
+        game_version::get_game_version,
+        discovery::discover_game_installs,
+        games::list_games,
+        games::register_game,
+        games::select_game,
+        hooks::run_hooks,
         proxy::set_proxy_addr,
         proxy::generate_ca_files,
         proxy::set_redirect_more,
+        profiles::list_profiles,
+        profiles::activate_profile,
+        config::set_config,
+        config::update_config,
+        config::load_config,
+        config::get_effective_config,
+        paths::get_data_dir,
+        settings::export_settings,
+        settings::import_settings,
         release::get_latest_release,
+        diagnostics::export_diagnostics,
         unzip::unzip,
         file_helpers::rename,
         file_helpers::dir_create,
@@ -247,6 +464,7 @@ fn main() -> Result<(), ArgsError> {
         downloader::stop_download,
         lang::get_lang,
         lang::get_languages,
+        logging::get_logs,
         web::valid_url,
         web::web_get,
         gamebanana::get_download_links,
@@ -256,7 +474,7 @@ fn main() -> Result<(), ArgsError> {
       .on_window_event(|event| {
         if let tauri::WindowEvent::CloseRequested { .. } = event.event() {
           // Ensure all proxy stuff is handled
-          disconnect();
+          disconnect_or_log();
         }
       })
       .run(tauri::generate_context!())
@@ -265,17 +483,94 @@ fn main() -> Result<(), ArgsError> {
     try_flush();
     println!("Press enter or CTRL-C twice to quit...");
     std::io::stdin().read_line(&mut String::new()).unwrap();
+
+    if let Some(watcher) = CLI_GAME_WATCHER.lock().unwrap().take() {
+      println!("Waiting for the game to close to run the post_exit hooks...");
+      if watcher.join().is_err() {
+        error!("Watching the game panicked");
+      }
+    }
   }
 
   // Always disconnect upon closing the program
-  disconnect();
+  disconnect_or_log();
 
   // Always unpatch game upon closing the program
-  block_on(patch::unpatch_game());
+  unpatch_or_log();
 
   Ok(())
 }
 
+/**
+ * Without the GUI there's nobody to start a process watcher, so the game launched from the
+ * command line is watched here, and the post_exit hooks run once it closes.
+ */
+fn watch_cli_game(game_exe: &str) {
+  let process = std::path::Path::new(game_exe)
+    .file_name()
+    .map(|name| name.to_string_lossy().to_string())
+    .unwrap_or_default();
+
+  let watcher = thread::spawn(move || {
+    // The other launcher runs the game in a thread, which ends with it
+    #[cfg(target_os = "linux")]
+    let game_thread = AAGL_THREAD.lock().unwrap().take();
+    #[cfg(not(target_os = "linux"))]
+    let game_thread: Option<thread::JoinHandle<()>> = None;
+
+    let closed = match game_thread {
+      Some(game_thread) => {
+        if game_thread.join().is_err() {
+          error!("Game thread panicked");
+        }
+        true
+      }
+      None => wait_for_exit(&process),
+    };
+    if closed {
+      info!("Game closed");
+      hooks::run_or_log(HookStage::PostExit);
+    }
+  });
+
+  *CLI_GAME_WATCHER.lock().unwrap() = Some(watcher);
+}
+
+// Whether `process` showed up and closed again. Gives up if it doesn't start within a minute.
+fn wait_for_exit(process: &str) -> bool {
+  let started = std::time::Instant::now();
+  let mut system = System::new();
+  let mut seen = false;
+
+  loop {
+    thread::sleep(std::time::Duration::from_secs(2));
+    system.refresh_processes();
+
+    let running = system.processes_by_exact_name(process).next().is_some();
+    if running {
+      seen = true;
+    } else if seen {
+      return true;
+    } else if started.elapsed() > std::time::Duration::from_secs(60) {
+      warn!("{} never started, not running the post_exit hooks", process);
+      return false;
+    }
+  }
+}
+
+// For cleanup paths that have nobody to report an error to
+fn disconnect_or_log() {
+  if let Err(e) = disconnect() {
+    error!("Failed to disconnect from proxy: {}", e);
+  }
+}
+
+fn unpatch_or_log() {
+  if let Err(e) = block_on(patch::unpatch_game(None)) {
+    error!("Failed to unpatch the game: {}", e);
+  }
+}
+
 #[tauri::command]
 fn is_game_running() -> bool {
   // Grab the game process name
@@ -293,7 +588,7 @@ fn enable_process_watcher(window: tauri::Window, process: String) {
     *WATCH_GAME_PROCESS.lock().unwrap() = "".to_string();
   });
 
-  println!("Starting process watcher...");
+  info!("Starting process watcher...");
 
   thread::spawn(move || {
     // Initial sleep for 8 seconds, since running 20 different injectors or whatever can take a while
@@ -317,12 +612,13 @@ fn enable_process_watcher(window: tauri::Window, process: String) {
 
         // If the game process closes, disable the proxy.
         if !exists {
-          println!("Game closed");
+          info!("Game closed");
 
           *WATCH_GAME_PROCESS.lock().unwrap() = "".to_string();
-          disconnect();
+          disconnect_or_log();
 
           window.emit("game_closed", &()).unwrap();
+          hooks::run_or_log(HookStage::PostExit);
           break;
         }
       }
@@ -345,19 +641,22 @@ fn enable_process_watcher(window: tauri::Window, process: String) {
       drop(lock);
       if end_time < Instant::now() {
         // If more than 60 seconds pass something has gone wrong
-        println!("Waiting for game thread timed out");
+        warn!("Waiting for game thread timed out");
         return;
       }
       // Otherwhise wait in order to not use too many CPU cycles
       sleep(Duration::from_millis(128));
     };
-    game_thread.join().unwrap();
-    println!("Game closed");
+    if game_thread.join().is_err() {
+      error!("Game thread panicked");
+    }
+    info!("Game closed");
 
     *WATCH_GAME_PROCESS.lock().unwrap() = "".to_string();
-    disconnect();
+    disconnect_or_log();
 
     window.emit("game_closed", &()).unwrap();
+    hooks::run_or_log(HookStage::PostExit);
   });
 }
 
@@ -410,7 +709,7 @@ fn restart_grasscutter(_window: tauri::Window) {
   // Placeholder text for imports
   let s = System::new();
   if let Some(process) = s.process(Pid::from(1337)) {
-    println!("{}", process.name());
+    debug!("{}", process.name());
   }
 }
 
@@ -425,7 +724,7 @@ fn enable_grasscutter_watcher(window: tauri::Window, process: String) {
     *WATCH_GRASSCUTTER_PROCESS.lock().unwrap() = "".to_string();
   });
 
-  println!("Starting grasscutter watcher...");
+  info!("Starting grasscutter watcher...");
 
   thread::spawn(move || {
     // Initial sleep for 1 second while Grasscutter opens
@@ -462,7 +761,7 @@ fn enable_grasscutter_watcher(window: tauri::Window, process: String) {
 
         // If the grasscutter process closes.
         if !exists {
-          println!("Grasscutter closed");
+          info!("Grasscutter closed");
 
           *WATCH_GRASSCUTTER_PROCESS.lock().unwrap() = "".to_string();
 
@@ -475,53 +774,61 @@ fn enable_grasscutter_watcher(window: tauri::Window, process: String) {
 }
 
 #[tauri::command]
-async fn connect(port: u16, certificate_path: String) {
+async fn connect(port: u16, certificate_path: String) -> Result<(), CultivationError> {
   // Log message to console.
-  println!("Connecting to proxy...");
+  info!("Connecting to proxy...");
 
   // Change proxy settings.
-  proxy::connect_to_proxy(port);
+  proxy::connect_to_proxy(port)?;
 
   // Create and start a proxy.
-  proxy::create_proxy(port, certificate_path).await;
+  proxy::create_proxy(port, certificate_path).await?;
+  proxy::set_connected(true);
+
+  Ok(())
 }
 
 #[tauri::command]
-fn disconnect() {
+fn disconnect() -> Result<(), CultivationError> {
   // Log message to console.
-  println!("Disconnecting from proxy...");
+  info!("Disconnecting from proxy...");
 
   // Change proxy settings.
-  proxy::disconnect_from_proxy();
+  proxy::disconnect_from_proxy()?;
+  proxy::set_connected(false);
+
+  Ok(())
 }
 
 #[tauri::command]
-async fn req_get(url: String) -> String {
+async fn req_get(url: String) -> Result<String, CultivationError> {
   // Send a GET request to the specified URL and send the response body back to the client.
   web::query(&url.to_string()).await
 }
 
 #[tauri::command]
-async fn get_theme_list(data_dir: String) -> Vec<HashMap<String, String>> {
+async fn get_theme_list(
+  data_dir: String,
+) -> Result<Vec<HashMap<String, String>>, CultivationError> {
   let theme_loc = format!("{}/themes", data_dir);
 
   // Ensure folder exists
   if !std::path::Path::new(&theme_loc).exists() {
-    std::fs::create_dir_all(&theme_loc).unwrap();
+    std::fs::create_dir_all(&theme_loc)?;
   }
 
   // Read each index.json folder in each theme folder
   let mut themes = Vec::new();
 
-  for entry in std::fs::read_dir(&theme_loc).unwrap() {
-    let entry = entry.unwrap();
+  for entry in std::fs::read_dir(&theme_loc)? {
+    let entry = entry?;
     let path = entry.path();
 
     if path.is_dir() {
       let index_path = format!("{}/index.json", path.to_str().unwrap());
 
       if std::path::Path::new(&index_path).exists() {
-        let theme_json = std::fs::read_to_string(&index_path).unwrap();
+        let theme_json = std::fs::read_to_string(&index_path)?;
 
         let mut map = HashMap::new();
 
@@ -534,5 +841,5 @@ async fn get_theme_list(data_dir: String) -> Vec<HashMap<String, String>> {
     }
   }
 
-  themes
+  Ok(themes)
 }
@@ -0,0 +1,456 @@
+use crate::error::CultivationError;
+use crate::games;
+use crate::rsa_key::{self, KeyRole, RsaPublicKey};
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+// Built from mhycrypto/metadata.cpp, see build.rs
+extern "C" {
+  fn decrypt_global_metadata(data: *mut u8, size: usize) -> i32;
+  fn encrypt_global_metadata(data: *mut u8, size: usize) -> i32;
+}
+
+// The keys live in the last 0x4000 bytes of the file
+const KEY_AREA_SIZE: usize = 0x4000;
+const KEY_MAGIC: [u8; 4] = [0x2e, 0xfc, 0xfe, 0x2c];
+const KEY_MAGIC_OFFSET: usize = 0xc8;
+const KEY_OFFSET_OFFSET: usize = 0xd2;
+// Short key and long key, read from the key offset on
+const KEYS_SIZE: usize = 0x10 + 0xb00;
+
+// Where the string literal table is described in the (decrypted) header
+const LITERAL_DATA_OFFSET_OFFSET: usize = 0x18;
+const LITERAL_TABLE_OFFSET_OFFSET: usize = 0x20;
+const LITERAL_TABLE_SIZE_OFFSET: usize = 0x24;
+
+const RSA_KEY_START: &[u8] = b"<RSAKeyValue>";
+const RSA_KEY_END: &[u8] = b"</RSAKeyValue>";
+
+/**
+ * Decrypts a global-metadata.dat in place.
+ * Checked up front, because the C++ side trusts the offsets it reads from the data.
+ */
+pub fn decrypt(data: &mut [u8]) -> Result<(), CultivationError> {
+  check_size(data)?;
+
+  let key_area = &data[data.len() - KEY_AREA_SIZE..];
+  if key_area[KEY_MAGIC_OFFSET..KEY_MAGIC_OFFSET + 4] != KEY_MAGIC {
+    return Err(CultivationError::Metadata(
+      "This is not an encrypted global-metadata.dat".to_string(),
+    ));
+  }
+
+  let key_offset =
+    u16::from_le_bytes([key_area[KEY_OFFSET_OFFSET], key_area[KEY_OFFSET_OFFSET + 1]]) as usize;
+  if key_offset + KEYS_SIZE > KEY_AREA_SIZE {
+    return Err(CultivationError::Metadata(format!(
+      "The key offset {:#x} points outside the file",
+      key_offset
+    )));
+  }
+
+  // Safe: the size and key offset were checked above, everything else is bounds checked in C++
+  match unsafe { decrypt_global_metadata(data.as_mut_ptr(), data.len()) } {
+    0 => Ok(()),
+    _ => Err(CultivationError::Metadata(
+      "The string literals could not be decrypted, the file may be damaged".to_string(),
+    )),
+  }
+}
+
+// Encrypts a decrypted global-metadata.dat in place, the opposite of decrypt()
+pub fn encrypt(data: &mut [u8]) -> Result<(), CultivationError> {
+  check_size(data)?;
+
+  // Safe: the size was checked above, everything else is bounds checked in C++
+  match unsafe { encrypt_global_metadata(data.as_mut_ptr(), data.len()) } {
+    0 => Ok(()),
+    _ => Err(CultivationError::Metadata(
+      "The string literals could not be encrypted, the file may be damaged".to_string(),
+    )),
+  }
+}
+
+// The encrypted blocks are spread over the file, and must not reach into the keys
+fn check_size(data: &[u8]) -> Result<(), CultivationError> {
+  let size = data.len();
+  let per_entry = size / 0x100 / 0x40;
+  let blocks_end = 0x40 * per_entry * 0xff + 0x40;
+
+  if size < KEY_AREA_SIZE || blocks_end > size - KEY_AREA_SIZE {
+    return Err(CultivationError::Metadata(format!(
+      "{} bytes is too small for a global-metadata.dat",
+      size
+    )));
+  }
+
+  Ok(())
+}
+
+/**
+ * Decrypts the global-metadata.dat at `path`, to `output` if given, or next to it otherwise.
+ * Returns the path of the decrypted file.
+ */
+#[tauri::command]
+pub fn decrypt_metadata(path: String, output: Option<String>) -> Result<String, CultivationError> {
+  convert_file(&path, output, "decrypted", decrypt)
+}
+
+#[tauri::command]
+pub fn encrypt_metadata(path: String, output: Option<String>) -> Result<String, CultivationError> {
+  convert_file(&path, output, "encrypted", encrypt)
+}
+
+fn convert_file(
+  path: &str,
+  output: Option<String>,
+  suffix: &str,
+  convert: fn(&mut [u8]) -> Result<(), CultivationError>,
+) -> Result<String, CultivationError> {
+  let output = match output {
+    Some(output) => PathBuf::from(output),
+    None => PathBuf::from(format!("{}.{}", path, suffix)),
+  };
+
+  let mut data = fs::read(path)?;
+  convert(&mut data)?;
+  fs::write(&output, data)?;
+
+  info!("Wrote {} {:?}", suffix, output);
+  Ok(output.to_string_lossy().to_string())
+}
+
+// <exe name>_Data/Managed/Metadata/global-metadata.dat
+pub fn metadata_path(game_exe: &Path) -> PathBuf {
+  games::data_folder(game_exe)
+    .join("Managed")
+    .join("Metadata")
+    .join("global-metadata.dat")
+}
+
+/**
+ * Decrypts the metadata at `path`, puts our RSA keys in, and encrypts it again.
+ * Returns None if it already has our keys.
+ */
+pub fn patched_metadata(path: &Path) -> Result<Option<Vec<u8>>, CultivationError> {
+  let mut data = fs::read(path)?;
+  decrypt(&mut data)?;
+
+  let keys = KeyRole::ALL
+    .iter()
+    .map(|role| Ok((*role, rsa_key::key_for(*role)?)))
+    .collect::<Result<Vec<_>, CultivationError>>()?;
+  let shipped = KeyRole::ALL
+    .iter()
+    .map(|role| Ok((*role, rsa_key::shipped_key(*role)?)))
+    .collect::<Result<Vec<_>, CultivationError>>()?;
+  if replace_rsa_keys(&mut data, &keys, &shipped)? == 0 {
+    return Ok(None);
+  }
+
+  encrypt(&mut data)?;
+  Ok(Some(data))
+}
+
+/**
+ * Overwrites the <RSAKeyValue> string literals in decrypted metadata with `keys`.
+ * Which key a literal holds is told by its modulus, see literal_role.
+ * String literals have a fixed length, so shorter keys are padded with whitespace.
+ * Returns how many keys were changed.
+ */
+pub fn replace_rsa_keys(
+  data: &mut [u8],
+  keys: &[(KeyRole, Vec<u8>)],
+  shipped: &[(KeyRole, Vec<u8>)],
+) -> Result<usize, CultivationError> {
+  let slots: Vec<Range<usize>> = string_literals(data)?
+    .into_iter()
+    .filter(|literal| data[literal.clone()].starts_with(RSA_KEY_START))
+    .collect();
+
+  // Anything else means a game update moved things around, and we'd be guessing
+  if slots.len() != KeyRole::ALL.len() {
+    return Err(CultivationError::Metadata(format!(
+      "Expected {} RSA keys in the metadata, found {}",
+      KeyRole::ALL.len(),
+      slots.len()
+    )));
+  }
+
+  let mut replacements = vec![];
+  for slot in slots {
+    let role = literal_role(&data[slot.clone()], keys, shipped)?;
+    if replacements.iter().any(|(_, other)| *other == role) {
+      return Err(CultivationError::Metadata(format!(
+        "The metadata has two {:?} keys, so they can't be told apart",
+        role
+      )));
+    }
+    replacements.push((slot, role));
+  }
+  debug!("Found the RSA keys in the metadata: {:?}", replacements);
+
+  let mut changed = 0;
+  for (slot, role) in replacements {
+    if let Some((_, key)) = keys.iter().find(|(key_role, _)| *key_role == role) {
+      let key = fit_key(key, slot.len())?;
+      if data[slot.clone()] != key[..] {
+        data[slot].copy_from_slice(&key);
+        changed += 1;
+      }
+    }
+  }
+
+  Ok(changed)
+}
+
+/**
+ * Which key an <RSAKeyValue> literal holds, by its modulus. One of ours means the metadata was
+ * patched before. Otherwise it's the game's own, which is the size of the key we ship for it.
+ */
+fn literal_role(
+  literal: &[u8],
+  keys: &[(KeyRole, Vec<u8>)],
+  shipped: &[(KeyRole, Vec<u8>)],
+) -> Result<KeyRole, CultivationError> {
+  let parse = |key: &[u8]| RsaPublicKey::parse(&String::from_utf8_lossy(key));
+  let key = parse(literal).map_err(|e| {
+    CultivationError::Metadata(format!("An RSA key in the metadata can't be read: {}", e))
+  })?;
+
+  let known = keys
+    .iter()
+    .chain(shipped)
+    .find(|(_, known)| parse(known).map_or(false, |known| known.modulus() == key.modulus()));
+  if let Some((role, _)) = known {
+    return Ok(*role);
+  }
+
+  let same_size: Vec<KeyRole> = shipped
+    .iter()
+    .filter(|(_, ours)| parse(ours).map_or(false, |ours| ours.bits() == key.bits()))
+    .map(|(role, _)| *role)
+    .collect();
+  match same_size[..] {
+    [role] => Ok(role),
+    _ => Err(CultivationError::Metadata(format!(
+      "The metadata has a {}-bit RSA key that's not one we know of",
+      key.bits()
+    ))),
+  }
+}
+
+// Byte ranges of every string literal, as listed in the header of decrypted metadata
+fn string_literals(data: &[u8]) -> Result<Vec<Range<usize>>, CultivationError> {
+  let read_u32 = |offset: usize| -> Option<usize> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+  };
+  let invalid = || CultivationError::Metadata("The string literal table is invalid".to_string());
+
+  let data_offset = read_u32(LITERAL_DATA_OFFSET_OFFSET).ok_or_else(invalid)?;
+  let table_offset = read_u32(LITERAL_TABLE_OFFSET_OFFSET).ok_or_else(invalid)?;
+  let table_size = read_u32(LITERAL_TABLE_SIZE_OFFSET).ok_or_else(invalid)?;
+
+  let mut literals = vec![];
+  for entry in (table_offset..table_offset + table_size).step_by(8) {
+    // Stored as the offset into the string literal data, then the length
+    let start = data_offset + read_u32(entry).ok_or_else(invalid)?;
+    let length = read_u32(entry + 4).ok_or_else(invalid)?;
+    if start + length > data.len() {
+      return Err(invalid());
+    }
+
+    literals.push(start..start + length);
+  }
+
+  Ok(literals)
+}
+
+fn fit_key(key: &[u8], length: usize) -> Result<Vec<u8>, CultivationError> {
+  let trimmed_len = key.len()
+    - key
+      .iter()
+      .rev()
+      .take_while(|byte| byte.is_ascii_whitespace())
+      .count();
+  let trimmed = &key[..trimmed_len];
+  if !trimmed.starts_with(RSA_KEY_START) || !trimmed.ends_with(RSA_KEY_END) {
+    return Err(CultivationError::Metadata(
+      "An RSA key is not in <RSAKeyValue> format".to_string(),
+    ));
+  }
+  if trimmed.len() > length {
+    return Err(CultivationError::Metadata(format!(
+      "An RSA key is {} bytes long, but the metadata only has room for {}",
+      trimmed.len(),
+      length
+    )));
+  }
+
+  // Whitespace before the closing tag is fine for the XML parser
+  let end = trimmed.len() - RSA_KEY_END.len();
+  let mut fitted = trimmed[..end].to_vec();
+  fitted.resize(length - RSA_KEY_END.len(), b' ');
+  fitted.extend_from_slice(RSA_KEY_END);
+
+  Ok(fitted)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Big enough for the encrypted blocks to stay clear of the key area
+  const SIZE: usize = 0x800000;
+  const LITERAL: &[u8] = b"<RSAKeyValue>test</RSAKeyValue>";
+
+  fn write_u32(data: &mut [u8], offset: usize, value: usize) {
+    data[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+  }
+
+  // Decrypted metadata with these string literals from 0x2000 on, the rest is filler
+  fn synthetic_metadata(literals: &[&[u8]]) -> Vec<u8> {
+    let mut data: Vec<u8> = (0..SIZE).map(|i| (i * 31 % 251) as u8).collect();
+    write_u32(&mut data, LITERAL_DATA_OFFSET_OFFSET, 0x2000);
+    write_u32(&mut data, LITERAL_TABLE_OFFSET_OFFSET, 0x1000);
+    write_u32(&mut data, LITERAL_TABLE_SIZE_OFFSET, literals.len() * 8);
+
+    let mut offset = 0;
+    for (i, literal) in literals.iter().enumerate() {
+      write_u32(&mut data, 0x1000 + i * 8, offset);
+      write_u32(&mut data, 0x1004 + i * 8, literal.len());
+      data[0x2000 + offset..0x2000 + offset + literal.len()].copy_from_slice(literal);
+      offset += literal.len();
+    }
+    data
+  }
+
+  // Stands in for one of the game's keys, with room to spare like the real ones
+  fn game_key(bits: usize) -> Vec<u8> {
+    let xml = format!(
+      "<RSAKeyValue><Modulus>{}</Modulus><Exponent>AQAB</Exponent></RSAKeyValue>",
+      base64::encode(vec![0xc5; bits / 8])
+    );
+    format!("{:<600}", xml).into_bytes()
+  }
+
+  fn shipped_keys() -> Vec<(KeyRole, Vec<u8>)> {
+    KeyRole::ALL
+      .iter()
+      .map(|role| {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+          .join("keys")
+          .join(role.file_name());
+        (*role, fs::read(path).unwrap())
+      })
+      .collect()
+  }
+
+  fn is_metadata_error<T>(result: Result<T, CultivationError>) -> bool {
+    matches!(result, Err(CultivationError::Metadata(_)))
+  }
+
+  #[test]
+  fn decrypting_undoes_encrypting() {
+    let original = synthetic_metadata(&[LITERAL]);
+    let mut data = original.clone();
+
+    encrypt(&mut data).unwrap();
+    assert_ne!(
+      data[..SIZE - KEY_AREA_SIZE],
+      original[..SIZE - KEY_AREA_SIZE]
+    );
+    decrypt(&mut data).unwrap();
+
+    // The key area is made up when encrypting
+    assert_eq!(
+      data[..SIZE - KEY_AREA_SIZE],
+      original[..SIZE - KEY_AREA_SIZE]
+    );
+    assert_eq!(
+      string_literals(&data).unwrap(),
+      vec![0x2000..0x2000 + LITERAL.len()]
+    );
+  }
+
+  #[test]
+  fn refuses_files_too_small_for_the_blocks() {
+    assert!(is_metadata_error(encrypt(&mut vec![0; KEY_AREA_SIZE - 1])));
+    assert!(is_metadata_error(decrypt(&mut vec![0; KEY_AREA_SIZE - 1])));
+    // Big enough for the key area, but the encrypted blocks would reach into it
+    assert!(is_metadata_error(encrypt(&mut vec![0; SIZE / 2])));
+  }
+
+  #[test]
+  fn refuses_to_decrypt_without_the_key_magic() {
+    assert!(is_metadata_error(decrypt(&mut synthetic_metadata(&[
+      LITERAL
+    ]))));
+  }
+
+  #[test]
+  fn refuses_a_key_offset_outside_the_file() {
+    let mut data = synthetic_metadata(&[LITERAL]);
+    encrypt(&mut data).unwrap();
+
+    let key_offset = SIZE - KEY_AREA_SIZE + KEY_OFFSET_OFFSET;
+    data[key_offset..key_offset + 2].copy_from_slice(&(KEY_AREA_SIZE as u16 - 1).to_le_bytes());
+    assert!(is_metadata_error(decrypt(&mut data)));
+  }
+
+  #[test]
+  fn refuses_string_literals_outside_the_file() {
+    let mut data = synthetic_metadata(&[LITERAL]);
+    write_u32(&mut data, 0x1004, SIZE);
+    assert!(is_metadata_error(string_literals(&data)));
+
+    let mut data = synthetic_metadata(&[LITERAL]);
+    write_u32(&mut data, LITERAL_TABLE_OFFSET_OFFSET, SIZE - 4);
+    assert!(is_metadata_error(string_literals(&data)));
+  }
+
+  #[test]
+  fn replaces_rsa_keys_by_their_size_not_their_order() {
+    let shipped = shipped_keys();
+    let mut data = synthetic_metadata(&[&game_key(1024), &game_key(2048)]);
+
+    assert_eq!(replace_rsa_keys(&mut data, &shipped, &shipped).unwrap(), 2);
+    let literals = string_literals(&data).unwrap();
+    let password = RsaPublicKey::parse(&String::from_utf8_lossy(&data[literals[0].clone()]));
+    let dispatch = RsaPublicKey::parse(&String::from_utf8_lossy(&data[literals[1].clone()]));
+    assert_eq!(password.unwrap().bits(), 1024);
+    assert_eq!(dispatch.unwrap().bits(), 2048);
+
+    // Already ours, so nothing changes the second time
+    assert_eq!(replace_rsa_keys(&mut data, &shipped, &shipped).unwrap(), 0);
+  }
+
+  #[test]
+  fn refuses_an_unexpected_number_of_rsa_keys() {
+    let shipped = shipped_keys();
+
+    let mut data = synthetic_metadata(&[&game_key(2048)]);
+    assert!(is_metadata_error(replace_rsa_keys(
+      &mut data, &shipped, &shipped
+    )));
+
+    let mut data = synthetic_metadata(&[&game_key(2048), &game_key(1024), &game_key(1024)]);
+    assert!(is_metadata_error(replace_rsa_keys(
+      &mut data, &shipped, &shipped
+    )));
+  }
+
+  #[test]
+  fn refuses_rsa_keys_it_cannot_tell_apart() {
+    let shipped = shipped_keys();
+    let mut data = synthetic_metadata(&[&game_key(2048), &game_key(2048)]);
+
+    assert!(is_metadata_error(replace_rsa_keys(
+      &mut data, &shipped, &shipped
+    )));
+  }
+}
@@ -1,100 +1,159 @@
 use crate::config;
+use crate::error::CultivationError;
 use crate::file_helpers;
-use crate::system_helpers;
-use std::path::PathBuf;
-
-#[cfg(target_os = "linux")]
+use crate::game_version;
+use crate::metadata;
+use crate::patch_journal::{self, JournalStep, PatchAction};
+use crate::patch_manifest::{self, ForeignPatch, PatchEntry};
+use crate::paths;
+use crate::rsa_key::{self, KeyRole};
+use crate::snapshots;
 use once_cell::sync::Lazy;
-#[cfg(target_os = "linux")]
-use std::sync::Arc;
-#[cfg(target_os = "linux")]
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+const PUBLIC_KEY_FILE: &str = "PublicKey.txt";
 
-#[cfg(target_os = "linux")]
-static PATCH_STATE: Lazy<Arc<Mutex<Option<PatchState>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+// Patching and unpatching (or repairing) must not run at the same time
+pub static PATCH_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
-#[cfg(target_os = "linux")]
 #[derive(Debug, Clone, Copy)]
 enum PatchState {
   NotExist,
   Same,
+  // Another launcher's patch is in place
+  Foreign,
   BakNotExist,
   BakExist,
 }
 
-#[cfg(target_os = "linux")]
 use PatchState::*;
 
-#[cfg(target_os = "linux")]
-impl PatchState {
-  fn to_wta(self) -> WhatToUnpach {
-    let (mhyp_renamed, game_was_patched) = match self {
-      NotExist => (false, true),
-      Same => (false, true),
-      BakNotExist => (true, true),
-      BakExist => (false, false),
-    };
-    WhatToUnpach {
-      mhyp_renamed,
-      game_was_patched,
-    }
+/**
+ * Copies the patch for the detected game version into the game directory, moving the original
+ * aside if there is one. Versions without a patch get our RSA keys put in their metadata instead.
+ * Every change is journaled first, see patch_journal, and the files are snapshotted beforehand.
+ * With `dry_run`, only returns what would be changed.
+ * Another launcher's patch is only swapped out for ours with `swap_foreign`, otherwise this fails
+ * with a foreign_patch error so the user can be asked first.
+ */
+#[cfg(any(windows, target_os = "linux"))]
+#[tauri::command]
+pub async fn patch_game(
+  dry_run: Option<bool>,
+  swap_foreign: Option<bool>,
+) -> Result<Vec<PatchAction>, CultivationError> {
+  let _lock = PATCH_LOCK.lock().await;
+
+  let dry_run = dry_run.unwrap_or(false);
+  let plan = plan_patch(dry_run, swap_foreign.unwrap_or(false)).await?;
+  if dry_run {
+    return Ok(plan);
+  }
+
+  snapshots::take(
+    "Patch game",
+    plan.iter().flat_map(|action| match action {
+      PatchAction::Rename { from, to } => vec![from, to],
+      PatchAction::Copy { to, .. } => vec![to],
+      PatchAction::Delete { .. } | PatchAction::Keep { .. } | PatchAction::KeepMoved { .. } => {
+        vec![]
+      }
+    }),
+  )?;
+
+  for action in &plan {
+    apply_patch(action)?;
   }
-}
 
-#[cfg(target_os = "linux")]
-#[derive(Debug, Clone)]
-struct WhatToUnpach {
-  mhyp_renamed: bool,
-  game_was_patched: bool,
+  Ok(plan)
 }
 
-#[cfg(windows)]
-#[tauri::command]
-pub async fn patch_game() -> bool {
-  let patch_path = PathBuf::from(system_helpers::install_location()).join("patch/version.dll");
+#[cfg(any(windows, target_os = "linux"))]
+async fn plan_patch(
+  dry_run: bool,
+  swap_foreign: bool,
+) -> Result<Vec<PatchAction>, CultivationError> {
+  // Patched earlier and still intact, eg. when launching the game a second time
+  let journal = patch_journal::load()?;
+  if !journal.steps.is_empty() {
+    let mut problems = vec![];
+    for step in &journal.steps {
+      problems.extend(step.problem()?);
+    }
+
+    if problems.is_empty() {
+      info!("The game is already patched.");
+      return Ok(vec![]);
+    }
+    return Err(CultivationError::Patch(format!(
+      "The game files changed since they were patched ({}). Repair the patch first.",
+      problems.join(", ")
+    )));
+  }
+
+  let game_path = require_game_rsa_path().await?;
+  let game_exe = game_version::game_exe()?;
+  let Some(entry) = patch_manifest::select_patch(&game_exe)? else {
+    // No DLL patch for this version, so put our keys in the metadata instead
+    return plan_metadata_patch(&game_exe, dry_run);
+  };
+  entry.verify_patch_file()?;
+  let patch_path = entry.patch_path();
+  let game_mhyp = PathBuf::from(&game_path).join(&entry.target);
 
   // Are we already patched with mhypbase? If so, that's fine, just continue as normal
-  let game_is_patched = file_helpers::are_files_identical(
-    patch_path.clone().to_str().unwrap(),
-    PathBuf::from(get_game_rsa_path().await.unwrap())
+  #[cfg(windows)]
+  if file_helpers::are_files_identical(
+    patch_path.to_str().unwrap(),
+    PathBuf::from(&game_path)
       .join("mhypbase.dll")
       .to_str()
       .unwrap(),
-  );
-
-  // Tell user they won't be unpatched with manual mhypbase patch
-  if game_is_patched {
-    println!(
-      "You are already patched using mhypbase, so you will not be auto patched and unpatched!"
-    );
-    return true;
+  ) {
+    // Tell user they won't be unpatched with manual mhypbase patch
+    info!("You are already patched using mhypbase, so you will not be auto patched and unpatched!");
+    return Ok(vec![]);
   }
 
-  // Copy the patch to game files
-  let replaced = file_helpers::copy_file_with_new_name(
-    patch_path.clone().to_str().unwrap().to_string(),
-    get_game_rsa_path().await.unwrap(),
-    String::from("version.dll"),
-  );
-
-  if !replaced {
-    return false;
-  }
+  let foreign = if game_mhyp.exists() {
+    patch_manifest::load_manifest(&game_exe)?
+      .find_foreign(&entry.target, &file_helpers::sha256_file(&game_mhyp)?)
+      .cloned()
+  } else {
+    None
+  };
 
-  true
+  let mut plan = plan_dll_patch(
+    &entry,
+    &patch_path,
+    Path::new(&game_path),
+    foreign,
+    swap_foreign,
+  )?;
+  plan.extend(plan_public_key(Path::new(&game_path), dry_run)?);
+  Ok(plan)
 }
 
-#[cfg(target_os = "linux")]
-#[tauri::command]
-pub async fn patch_game() -> bool {
-  let mut patch_state_mutex = PATCH_STATE.lock().await;
-  if patch_state_mutex.is_some() {
-    println!("Game already patched!");
-  }
-
-  let patch_path = PathBuf::from(system_helpers::install_location()).join("patch/version.dll");
-  let game_mhyp = PathBuf::from(get_game_rsa_path().await.unwrap()).join("mhypbase.dll");
-  let game_mhyp_bak = PathBuf::from(get_game_rsa_path().await.unwrap()).join("mhypbase.dll.bak");
+/**
+ * Works out how to put `patch_path` in place as `entry.target` in the game directory,
+ * depending on what's there already.
+ */
+#[cfg(any(windows, target_os = "linux"))]
+fn plan_dll_patch(
+  entry: &PatchEntry,
+  patch_path: &Path,
+  game_path: &Path,
+  mut foreign: Option<ForeignPatch>,
+  swap_foreign: bool,
+) -> Result<Vec<PatchAction>, CultivationError> {
+  let game_mhyp = game_path.join(&entry.target);
+  let game_mhyp_bak = game_path.join(format!("{}.bak", entry.target));
+  let game_mhyp_foreign = game_path.join(format!("{}.foreign", entry.target));
 
   let patch_state = if !game_mhyp.exists() {
     NotExist
@@ -103,119 +162,471 @@ pub async fn patch_game() -> bool {
     game_mhyp.to_str().unwrap(),
   ) {
     Same
+  } else if foreign.is_some() {
+    Foreign
   } else if !game_mhyp_bak.exists() {
     BakNotExist
+  } else if entry.original_sha256.is_none() || entry.is_original(&game_mhyp_bak)? {
+    // Not in the manifest, but it moved the original aside the same way we do. Without a hash to
+    // check the .bak against that's a guess, but swapping it out leaves both files as they are.
+    foreign = Some(ForeignPatch::unlisted(&entry.target, &game_mhyp)?);
+    Foreign
   } else {
     BakExist
   };
+  debug!("Patch state for {:?}: {:?}", game_mhyp, patch_state);
 
-  match patch_state {
-    NotExist => {
-      // No renaming needed.
-      // Copy version.dll as mhypbase.dll
-      file_helpers::copy_file_with_new_name(
-        patch_path.clone().to_str().unwrap().to_string(),
-        get_game_rsa_path().await.unwrap(),
-        String::from("mhypbase.dll"),
-      );
-    }
+  let copy_patch = PatchAction::Copy {
+    from: patch_path.to_path_buf(),
+    to: game_mhyp.clone(),
+  };
+
+  let plan = match patch_state {
+    // No renaming needed.
+    NotExist => vec![copy_patch],
+    // No renaming needed.
+    // No copying needed, but it's still ours to remove when unpatching
     Same => {
-      // No renaming needed.
-      // No copying needed.
-      println!("The game is already patched.");
+      let mut plan = vec![];
+
+      // Patched by hand, or by a run whose journal is gone. The original goes back when unpatching.
+      if game_mhyp_bak.exists() {
+        if entry.original_sha256.is_some() && !entry.is_original(&game_mhyp_bak)? {
+          warn!(
+            "{:?} is not the original {}, so it won't be put back when unpatching",
+            game_mhyp_bak, entry.target
+          );
+        } else {
+          plan.push(PatchAction::KeepMoved {
+            from: game_mhyp.clone(),
+            to: game_mhyp_bak,
+          });
+        }
+      }
+
+      plan.push(PatchAction::Keep { path: game_mhyp });
+      plan
+    }
+    Foreign => {
+      let foreign = foreign.unwrap();
+      if !swap_foreign {
+        return Err(CultivationError::ForeignPatch(format!(
+          "The game is patched by {}. Its {} can be put aside while playing, and is put back \
+           when unpatching.",
+          foreign.name, entry.target
+        )));
+      }
+      if game_mhyp_foreign.exists() {
+        return Err(CultivationError::Patch(format!(
+          "{:?} is in the way of putting {}'s patch aside. Delete it if it's not needed.",
+          game_mhyp_foreign, foreign.name
+        )));
+      }
+
+      // Leave their backup of the original alone, it's theirs to restore
+      vec![
+        PatchAction::Rename {
+          from: game_mhyp,
+          to: game_mhyp_foreign,
+        },
+        copy_patch,
+      ]
     }
     BakNotExist => {
-      // The current mhypbase.dll is most likely the original
-
-      // Rename mhypbase.dll to mhypbase.dll.bak
-      file_helpers::rename(
-        game_mhyp.to_str().unwrap().to_string(),
-        game_mhyp_bak
-          .file_name()
-          .unwrap()
-          .to_str()
-          .unwrap()
-          .to_string(),
-      );
-      // Copy version.dll as mhypbase.dll
-      file_helpers::copy_file_with_new_name(
-        patch_path.clone().to_str().unwrap().to_string(),
-        get_game_rsa_path().await.unwrap(),
-        String::from("mhypbase.dll"),
-      );
+      // The current mhypbase.dll is most likely the original. Without a hash to check that, it's
+      // moved aside all the same, it's put back when unpatching either way.
+      if entry.original_sha256.is_some() && !entry.is_original(&game_mhyp)? {
+        return Err(CultivationError::Patch(format!(
+          "{} is not the original file this patch was made for, so it was left alone.",
+          entry.target
+        )));
+      }
+
+      // Rename mhypbase.dll to mhypbase.dll.bak, then copy version.dll as mhypbase.dll
+      vec![
+        PatchAction::Rename {
+          from: game_mhyp,
+          to: game_mhyp_bak,
+        },
+        copy_patch,
+      ]
     }
     BakExist => {
       // Can't rename. mhypbase.dll.bak already exists.
-      // Can't patch. mhypbase.dll exists.
-      // This SHOULD NOT HAPPEN
-      println!("The game directory contains a mhypbase.dll, but it's different from the patch.");
-      println!("Make sure you have the original mhypbase.dll.");
-      println!("Delete any other copy, and place the original copy in the game directory with the original name.");
+      // Can't patch. mhypbase.dll exists, and it's not a patch we know of.
+      return Err(CultivationError::Patch(format!(
+        "The game directory contains a {0} and a {0}.bak, and the {0} is not a patch we \
+         recognize. Make sure you have the original {0}. Delete any other copy, and place the \
+         original copy in the game directory with the original name.",
+        entry.target
+      )));
+    }
+  };
+
+  Ok(plan)
+}
+
+/**
+ * The DLL patch reads a custom key for encrypting the password from PublicKey.txt next to the
+ * game, so the active profile's password key goes there.
+ */
+#[cfg(any(windows, target_os = "linux"))]
+fn plan_public_key(game_path: &Path, dry_run: bool) -> Result<Vec<PatchAction>, CultivationError> {
+  // The server would use its own dispatch key, which the DLL patch can't give the game
+  if rsa_key::profile_key(KeyRole::Dispatch)?.is_some() {
+    return Err(CultivationError::RsaKey(
+      "The DLL patch for this game version only takes a password key, remove the profile's dispatch key to use it"
+        .to_string(),
+    ));
+  }
+  let Some(key) = rsa_key::profile_key(KeyRole::Password)? else {
+    return Ok(vec![]);
+  };
+
+  let game_key = game_path.join(PUBLIC_KEY_FILE);
+  let game_key_bak = game_path.join(format!("{}.bak", PUBLIC_KEY_FILE));
+  let staged_key = paths::data_dir().join("patch").join(PUBLIC_KEY_FILE);
+  let mut plan = vec![];
+
+  if game_key.exists() {
+    // Put there by hand, so it's not ours to remove
+    if fs::read_to_string(&game_key)?.trim() == key {
+      return Ok(vec![]);
+    }
+    if game_key_bak.exists() {
+      return Err(CultivationError::Patch(format!(
+        "Both {:?} and {:?} exist. Delete whichever isn't needed.",
+        game_key, game_key_bak
+      )));
     }
+
+    plan.push(PatchAction::Rename {
+      from: game_key.clone(),
+      to: game_key_bak,
+    });
+  }
+
+  if !dry_run {
+    fs::create_dir_all(staged_key.parent().unwrap())?;
+    fs::write(&staged_key, key)?;
   }
 
-  patch_state_mutex.replace(patch_state);
-  patch_state.to_wta().game_was_patched
+  plan.push(PatchAction::Copy {
+    from: staged_key,
+    to: game_key,
+  });
+  Ok(plan)
 }
 
-#[cfg(windows)]
+/**
+ * Backs up global-metadata.dat and replaces it with a copy that has our RSA keys.
+ * The patched copy is made in the data dir first, except for dry runs.
+ */
+#[cfg(any(windows, target_os = "linux"))]
+fn plan_metadata_patch(
+  game_exe: &Path,
+  dry_run: bool,
+) -> Result<Vec<PatchAction>, CultivationError> {
+  let game_metadata = metadata::metadata_path(game_exe);
+  let game_metadata_bak = game_metadata.with_extension("dat.bak");
+  let patched_metadata = paths::data_dir()
+    .join("metadata")
+    .join("global-metadata.dat");
+
+  if !game_metadata.is_file() {
+    return Err(CultivationError::Patch(format!(
+      "There is no patch for this game version, and {:?} was not found to patch instead",
+      game_metadata
+    )));
+  }
+  if game_metadata_bak.exists() {
+    return Err(CultivationError::Patch(format!(
+      "{:?} already exists. Make sure global-metadata.dat is the original, then delete the backup.",
+      game_metadata_bak
+    )));
+  }
+
+  let Some(data) = metadata::patched_metadata(&game_metadata)? else {
+    info!("The metadata already has our RSA keys, so it will not be auto patched and unpatched!");
+    return Ok(vec![]);
+  };
+
+  if !dry_run {
+    fs::create_dir_all(patched_metadata.parent().unwrap())?;
+    fs::write(&patched_metadata, data)?;
+  }
+
+  Ok(vec![
+    PatchAction::Rename {
+      from: game_metadata.clone(),
+      to: game_metadata_bak,
+    },
+    PatchAction::Copy {
+      from: patched_metadata,
+      to: game_metadata,
+    },
+  ])
+}
+
+// Journals the action, then carries it out
+#[cfg(any(windows, target_os = "linux"))]
+fn apply_patch(action: &PatchAction) -> Result<(), CultivationError> {
+  match action {
+    PatchAction::Rename { from, to } => {
+      patch_journal::record(JournalStep::Renamed {
+        from: from.clone(),
+        to: to.clone(),
+        sha256: file_helpers::sha256_file(from)?,
+      })?;
+      fs::rename(from, to)?;
+    }
+    PatchAction::Copy { from, to } => {
+      // Copied next to the target first, so neither the journal nor the target ever has a
+      // partial copy. A crash before the rename leaves a step that restoring skips.
+      let tmp = to.with_file_name(format!(
+        "{}.tmp",
+        to.file_name().unwrap_or_default().to_string_lossy()
+      ));
+      let copied = fs::copy(from, &tmp)
+        .map_err(CultivationError::from)
+        .and_then(|_| file_helpers::sha256_file(&tmp))
+        .and_then(|sha256| {
+          patch_journal::record(JournalStep::Copied {
+            path: to.clone(),
+            sha256,
+          })
+        })
+        .and_then(|_| Ok(fs::rename(&tmp, to)?));
+      if copied.is_err() {
+        let _ = fs::remove_file(&tmp);
+      }
+      copied?;
+    }
+    PatchAction::Keep { path } => {
+      patch_journal::record(JournalStep::Copied {
+        path: path.clone(),
+        sha256: file_helpers::sha256_file(path)?,
+      })?;
+    }
+    PatchAction::KeepMoved { from, to } => {
+      patch_journal::record(JournalStep::Renamed {
+        from: from.clone(),
+        to: to.clone(),
+        sha256: file_helpers::sha256_file(to)?,
+      })?;
+    }
+    // Patching never deletes
+    PatchAction::Delete { .. } => {}
+  }
+
+  info!("{}", action);
+  Ok(())
+}
+
+// Puts back whatever patch_game changed, using the journal so it works after a restart too
 #[tauri::command]
-pub async fn unpatch_game() -> bool {
-  // Just delete patch since it's not replacing any existing file
-  let deleted = file_helpers::delete_file(
-    PathBuf::from(get_game_rsa_path().await.unwrap())
-      .join("version.dll")
-      .to_str()
-      .unwrap()
-      .to_string(),
-  );
+pub async fn unpatch_game(dry_run: Option<bool>) -> Result<Vec<PatchAction>, CultivationError> {
+  let _lock = PATCH_LOCK.lock().await;
+
+  let plan = patch_journal::plan_restore()?;
+  if dry_run.unwrap_or(false) {
+    return Ok(plan);
+  }
+
+  patch_journal::restore()?;
+  Ok(plan)
+}
 
-  deleted
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchStatus {
+  Clean,
+  PatchedByUs,
+  // A known patch is in place, but we didn't put it there
+  PatchedManually,
+  // Something's off, eg. an original is missing or a file we patched was changed since
+  Inconsistent,
 }
 
-#[cfg(target_os = "linux")]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KnownFile {
+  Original,
+  Patch,
+  // Another launcher's patch
+  Foreign,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PatchFile {
+  pub path: PathBuf,
+  // Unset if the file doesn't exist
+  pub sha256: Option<String>,
+  // Unset if it's neither a known original nor a known patch
+  pub known: Option<KnownFile>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PatchStatusReport {
+  pub status: PatchStatus,
+  pub files: Vec<PatchFile>,
+  // What makes it inconsistent
+  pub problems: Vec<String>,
+}
+
+/**
+ * Looks at the DLLs in the game directory that patching can touch, and works out whether the game
+ * is patched, and by whom. Every file is hashed and compared against the patch manifest.
+ */
 #[tauri::command]
-pub async fn unpatch_game() -> bool {
-  // TODO: Prevent the launcher from unpatching the game two times
-  // This might be related to redirecting calls from the ts version of
-  // unpatchGame to the rust version
-  let mut patch_state_mutex = PATCH_STATE.lock().await;
-  let patch_state = patch_state_mutex.take();
-  if patch_state.is_none() {
-    println!("Game not patched!");
-    // NOTE: true is returned since otherwhise the launcher thinks unpatching failed
-    // NOTE: actually it should be false since delete_file always returns false
-    return false;
-  }
-  let patch_state = patch_state.unwrap();
-
-  let game_mhyp = PathBuf::from(get_game_rsa_path().await.unwrap()).join("mhypbase.dll");
-  let game_mhyp_bak = PathBuf::from(get_game_rsa_path().await.unwrap()).join("mhypbase.dll.bak");
-
-  let WhatToUnpach {
-    mhyp_renamed,
-    game_was_patched,
-  } = patch_state.to_wta();
-
-  // If the current mhypbase.dll is the patch, then delete it.
-  let deleted = if game_was_patched {
-    file_helpers::delete_file(game_mhyp.to_str().unwrap().to_string());
-    true
+pub async fn patch_status() -> Result<PatchStatusReport, CultivationError> {
+  let _lock = PATCH_LOCK.lock().await;
+
+  let game_path = PathBuf::from(require_game_rsa_path().await?);
+  let manifest = patch_manifest::load_manifest(&game_version::game_exe()?)?;
+  let journal = patch_journal::load()?;
+
+  let known_patches: BTreeSet<String> = manifest
+    .patches
+    .iter()
+    .map(|entry| entry.patch_sha256.to_lowercase())
+    .collect();
+  let known_foreign: BTreeSet<String> = manifest
+    .foreign_patches
+    .iter()
+    .map(|foreign| foreign.sha256.to_lowercase())
+    .collect();
+  let known_originals: BTreeSet<String> = manifest
+    .patches
+    .iter()
+    .filter_map(|entry| entry.original_sha256.as_ref())
+    .map(|sha256| sha256.to_lowercase())
+    .collect();
+
+  // Every file a patch for this platform could be copied to, and where the original goes
+  let mut paths = BTreeSet::new();
+  for entry in manifest.patches.iter().filter(|entry| {
+    entry
+      .platform
+      .as_deref()
+      .map_or(true, |p| p == std::env::consts::OS)
+  }) {
+    paths.insert(game_path.join(&entry.target));
+    paths.insert(game_path.join(format!("{}.bak", entry.target)));
+    paths.insert(game_path.join(format!("{}.foreign", entry.target)));
+  }
+  // The manual way of patching on Windows
+  #[cfg(windows)]
+  paths.insert(game_path.join("mhypbase.dll"));
+
+  let mut files = vec![];
+  for path in paths {
+    files.push(inspect_file(
+      &path,
+      &known_originals,
+      &known_patches,
+      &known_foreign,
+    )?);
+  }
+
+  // Not in the manifest, but the original moved aside next to it gives it away
+  let moved_originals: Vec<PathBuf> = files
+    .iter()
+    .filter(|file| matches!(file.known, Some(KnownFile::Original)))
+    .filter(|file| file.path.extension().map_or(false, |ext| ext == "bak"))
+    .map(|file| file.path.with_extension(""))
+    .collect();
+  for file in &mut files {
+    if file.known.is_none() && file.sha256.is_some() && moved_originals.contains(&file.path) {
+      file.known = Some(KnownFile::Foreign);
+    }
+  }
+
+  let mut problems = vec![];
+  for step in &journal.steps {
+    problems.extend(step.problem()?);
+  }
+
+  // An original that was moved aside, without us having done it
+  for file in &files {
+    let is_bak = file.path.extension().map_or(false, |ext| ext == "bak");
+    let ours = journal
+      .steps
+      .iter()
+      .any(|step| matches!(step, JournalStep::Renamed { to, .. } if to == &file.path));
+    if is_bak && file.sha256.is_some() && !ours {
+      let original = file.path.with_extension("");
+      if !files
+        .iter()
+        .any(|f| f.path == original && f.sha256.is_some())
+      {
+        problems.push(format!(
+          "{:?} was moved to {:?} and nothing took its place",
+          original, file.path
+        ));
+      }
+    }
+  }
+
+  let status = if !problems.is_empty() {
+    PatchStatus::Inconsistent
+  } else if !journal.steps.is_empty() {
+    PatchStatus::PatchedByUs
+  } else if files
+    .iter()
+    .any(|file| matches!(file.known, Some(KnownFile::Patch | KnownFile::Foreign)))
+  {
+    PatchStatus::PatchedManually
   } else {
-    false
+    PatchStatus::Clean
   };
-  // If we renamed the original mhypbase.dll to mhypbase.dll.bak
-  // rename mhypbase.dll.bak back to mhypbase.dll
-  if mhyp_renamed {
-    file_helpers::rename(
-      game_mhyp_bak.to_str().unwrap().to_string(),
-      game_mhyp.to_str().unwrap().to_string(),
-    )
+
+  Ok(PatchStatusReport {
+    status,
+    files,
+    problems,
+  })
+}
+
+fn inspect_file(
+  path: &Path,
+  known_originals: &BTreeSet<String>,
+  known_patches: &BTreeSet<String>,
+  known_foreign: &BTreeSet<String>,
+) -> Result<PatchFile, CultivationError> {
+  if !path.is_file() {
+    return Ok(PatchFile {
+      path: path.to_path_buf(),
+      sha256: None,
+      known: None,
+    });
   }
 
-  // NOTE: As mentioned in a note above, false should be returned if the function succeded
-  // and true if it failed
-  !deleted
+  let sha256 = file_helpers::sha256_file(path)?;
+  let known = if known_patches.contains(&sha256) {
+    Some(KnownFile::Patch)
+  } else if known_foreign.contains(&sha256) {
+    Some(KnownFile::Foreign)
+  } else if known_originals.contains(&sha256) {
+    Some(KnownFile::Original)
+  } else {
+    None
+  };
+
+  Ok(PatchFile {
+    path: path.to_path_buf(),
+    sha256: Some(sha256),
+    known,
+  })
+}
+
+// Short description of what we currently have patched, for diagnostics
+pub async fn patch_state_description() -> String {
+  match patch_journal::load() {
+    Ok(journal) if journal.steps.is_empty() => "not patched".to_string(),
+    Ok(journal) => format!("patched ({:?})", journal.steps),
+    Err(e) => format!("patch journal unreadable: {}", e),
+  }
 }
 
 pub async fn get_game_rsa_path() -> Option<String> {
@@ -228,3 +639,172 @@ pub async fn get_game_rsa_path() -> Option<String> {
 
   Some(format!("{}/", game_folder.to_str().unwrap()).replace('\\', "/"))
 }
+
+async fn require_game_rsa_path() -> Result<String, CultivationError> {
+  get_game_rsa_path()
+    .await
+    .ok_or_else(|| CultivationError::Config("game_install_path is not set".to_string()))
+}
+
+#[cfg(all(test, any(windows, target_os = "linux")))]
+mod tests {
+  use super::*;
+
+  fn game_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cultivation-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn entry(original_sha256: Option<String>) -> PatchEntry {
+    PatchEntry {
+      min_version: None,
+      max_version: None,
+      platform: None,
+      file: "version.dll".to_string(),
+      target: "mhypbase.dll".to_string(),
+      original_sha256,
+      patch_sha256: String::new(),
+    }
+  }
+
+  #[test]
+  fn puts_back_the_original_of_a_patch_without_journal() {
+    let dir = game_dir("no-journal");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll"), b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll.bak"), b"original").unwrap();
+
+    let plan = plan_dll_patch(&entry(None), &patch, &dir, None, false).unwrap();
+
+    assert!(matches!(
+      &plan[..],
+      [PatchAction::KeepMoved { from, to }, PatchAction::Keep { path }]
+        if from == &dir.join("mhypbase.dll")
+          && to == &dir.join("mhypbase.dll.bak")
+          && path == &dir.join("mhypbase.dll")
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn leaves_a_backup_that_is_not_the_original() {
+    let dir = game_dir("wrong-backup");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll"), b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll.bak"), b"something else").unwrap();
+
+    let plan = plan_dll_patch(&entry(Some("0".repeat(64))), &patch, &dir, None, false).unwrap();
+
+    assert!(matches!(&plan[..], [PatchAction::Keep { .. }]));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn moves_the_original_aside_without_a_hash_to_check_it() {
+    let dir = game_dir("bak-not-exist");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll"), b"original").unwrap();
+
+    let plan = plan_dll_patch(&entry(None), &patch, &dir, None, false).unwrap();
+
+    assert!(matches!(
+      &plan[..],
+      [PatchAction::Rename { from, to }, PatchAction::Copy { .. }]
+        if from == &dir.join("mhypbase.dll") && to == &dir.join("mhypbase.dll.bak")
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn leaves_a_file_that_is_not_the_original() {
+    let dir = game_dir("not-original");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll"), b"something else").unwrap();
+
+    let result = plan_dll_patch(&entry(Some("0".repeat(64))), &patch, &dir, None, false);
+
+    assert!(matches!(result, Err(CultivationError::Patch(_))));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn copies_the_patch_when_there_is_nothing_to_replace() {
+    let dir = game_dir("not-exist");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+
+    let plan = plan_dll_patch(&entry(None), &patch, &dir, None, false).unwrap();
+
+    assert!(matches!(
+      &plan[..],
+      [PatchAction::Copy { from, to }] if from == &patch && to == &dir.join("mhypbase.dll")
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn recognizes_an_unlisted_patch_by_the_original_next_to_it() {
+    let dir = game_dir("unlisted-foreign");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll"), b"their patch").unwrap();
+    fs::write(dir.join("mhypbase.dll.bak"), b"original").unwrap();
+    let entry = entry(Some(
+      file_helpers::sha256_file(dir.join("mhypbase.dll.bak")).unwrap(),
+    ));
+
+    assert!(matches!(
+      plan_dll_patch(&entry, &patch, &dir, None, false),
+      Err(CultivationError::ForeignPatch(_))
+    ));
+
+    let plan = plan_dll_patch(&entry, &patch, &dir, None, true).unwrap();
+    assert!(matches!(
+      &plan[..],
+      [PatchAction::Rename { from, to }, PatchAction::Copy { .. }]
+        if from == &dir.join("mhypbase.dll") && to == &dir.join("mhypbase.dll.foreign")
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn offers_to_swap_an_unknown_file_next_to_a_backup_without_a_hash() {
+    let dir = game_dir("unhashed-foreign");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll"), b"their patch").unwrap();
+    fs::write(dir.join("mhypbase.dll.bak"), b"original").unwrap();
+
+    assert!(matches!(
+      plan_dll_patch(&entry(None), &patch, &dir, None, false),
+      Err(CultivationError::ForeignPatch(_))
+    ));
+    let plan = plan_dll_patch(&entry(None), &patch, &dir, None, true).unwrap();
+    assert!(matches!(
+      &plan[..],
+      [PatchAction::Rename { to, .. }, PatchAction::Copy { .. }]
+        if to == &dir.join("mhypbase.dll.foreign")
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn refuses_an_unknown_file_next_to_an_unknown_backup() {
+    let dir = game_dir("bak-exist");
+    let patch = dir.join("version.dll");
+    fs::write(&patch, b"patch").unwrap();
+    fs::write(dir.join("mhypbase.dll"), b"their patch").unwrap();
+    fs::write(dir.join("mhypbase.dll.bak"), b"something else").unwrap();
+
+    let result = plan_dll_patch(&entry(Some("0".repeat(64))), &patch, &dir, None, true);
+
+    assert!(matches!(result, Err(CultivationError::Patch(_))));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
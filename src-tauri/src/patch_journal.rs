@@ -0,0 +1,318 @@
+use crate::error::CultivationError;
+use crate::file_helpers;
+use crate::patch;
+use crate::paths;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use sysinfo::{Pid, System, SystemExt};
+use tracing::{info, warn};
+
+const JOURNAL_FILE: &str = "patch_journal.json";
+
+/**
+ * A file operation done while patching the game. Steps are recorded before they are carried out,
+ * so a crash in between leaves enough behind to put the original files back.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalStep {
+  // An original file was moved out of the way, eg. mhypbase.dll -> mhypbase.dll.bak
+  Renamed {
+    from: PathBuf,
+    to: PathBuf,
+    sha256: String,
+  },
+  // The patch was copied to `path`
+  Copied {
+    path: PathBuf,
+    sha256: String,
+  },
+}
+
+/**
+ * A change to the game directory, as planned by patching or unpatching.
+ * Dry runs return these instead of carrying them out.
+ */
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PatchAction {
+  Rename { from: PathBuf, to: PathBuf },
+  Copy { from: PathBuf, to: PathBuf },
+  Delete { path: PathBuf },
+  // Already the patch. Nothing is changed, but it's removed again when unpatching.
+  Keep { path: PathBuf },
+  // Already moved aside, eg. by patching by hand. Nothing is changed, but it's put back when
+  // unpatching.
+  KeepMoved { from: PathBuf, to: PathBuf },
+}
+
+impl fmt::Display for PatchAction {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PatchAction::Rename { from, to } => write!(f, "Rename {:?} to {:?}", from, to),
+      PatchAction::Copy { from, to } => write!(f, "Copy {:?} to {:?}", from, to),
+      PatchAction::Delete { path } => write!(f, "Delete {:?}", path),
+      PatchAction::Keep { path } => write!(f, "Keep {:?}", path),
+      PatchAction::KeepMoved { from, to } => write!(f, "Keep {:?} moved to {:?}", from, to),
+    }
+  }
+}
+
+impl JournalStep {
+  // Why the game directory doesn't look like this step was carried out, if it doesn't
+  pub fn problem(&self) -> Result<Option<String>, CultivationError> {
+    let (path, sha256) = match self {
+      JournalStep::Renamed { to, sha256, .. } => (to, sha256),
+      JournalStep::Copied { path, sha256 } => (path, sha256),
+    };
+
+    if !path.exists() {
+      return Ok(Some(format!("{:?} is missing", path)));
+    }
+    if !hash_matches(path, sha256)? {
+      return Ok(Some(format!("{:?} was changed since we patched", path)));
+    }
+
+    Ok(None)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PatchJournal {
+  pub steps: Vec<JournalStep>,
+  // The Cultivation that patched, which may still be running with the game
+  #[serde(default)]
+  pub owner_pid: Option<u32>,
+}
+
+fn journal_path() -> PathBuf {
+  paths::data_dir().join(JOURNAL_FILE)
+}
+
+// An empty journal when there's nothing to undo
+pub fn load() -> Result<PatchJournal, CultivationError> {
+  match fs::read_to_string(journal_path()) {
+    Ok(raw) => Ok(serde_json::from_str(&raw)?),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PatchJournal::default()),
+    Err(e) => Err(e.into()),
+  }
+}
+
+pub fn record(step: JournalStep) -> Result<(), CultivationError> {
+  let mut journal = load()?;
+  journal.steps.push(step);
+  journal.owner_pid = Some(std::process::id());
+
+  save(&journal)
+}
+
+fn save(journal: &PatchJournal) -> Result<(), CultivationError> {
+  let path = journal_path();
+  let tmp_path = path.with_extension("json.tmp");
+
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+
+  {
+    let mut tmp = fs::File::create(&tmp_path)?;
+    tmp.write_all(serde_json::to_string_pretty(journal)?.as_bytes())?;
+    tmp.sync_all()?;
+  }
+
+  fs::rename(&tmp_path, &path)?;
+  Ok(())
+}
+
+fn clear() -> Result<(), CultivationError> {
+  match fs::remove_file(journal_path()) {
+    Ok(_) => Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(e.into()),
+  }
+}
+
+/**
+ * Undoes the journal from the last step to the first, then removes it.
+ * Steps that were recorded but never carried out are skipped. If a file isn't what the journal
+ * says it should be, it is left alone and the journal is kept, so nothing is lost.
+ * Returns a description of every change made.
+ */
+pub fn restore() -> Result<Vec<String>, CultivationError> {
+  let mut journal = load()?;
+  let mut restored = vec![];
+
+  while let Some(step) = journal.steps.last().cloned() {
+    let result = plan_undo(&step, &|path| path.exists()).and_then(|action| match action {
+      Some(action) => apply_undo(&action).map(|_| Some(action)),
+      None => Ok(None),
+    });
+
+    match result {
+      Ok(Some(action)) => {
+        info!("{}", action);
+        restored.push(action.to_string());
+      }
+      Ok(None) => {}
+      Err(e) => {
+        // Keep what's left, so it can be retried once the file is sorted out
+        save(&journal)?;
+        return Err(e);
+      }
+    }
+
+    journal.steps.pop();
+  }
+
+  clear()?;
+  Ok(restored)
+}
+
+// What restore() would do right now, without doing it
+pub fn plan_restore() -> Result<Vec<PatchAction>, CultivationError> {
+  let journal = load()?;
+  let mut plan = vec![];
+
+  // Nothing is touched, so keep track of what the earlier actions would have moved
+  let mut gone: HashSet<PathBuf> = HashSet::new();
+  let mut added: HashSet<PathBuf> = HashSet::new();
+
+  for step in journal.steps.iter().rev() {
+    let exists = |path: &Path| !gone.contains(path) && (added.contains(path) || path.exists());
+    let Some(action) = plan_undo(step, &exists)? else {
+      continue;
+    };
+
+    match &action {
+      PatchAction::Rename { from, to } => {
+        gone.insert(from.clone());
+        added.remove(from);
+        gone.remove(to);
+        added.insert(to.clone());
+      }
+      PatchAction::Delete { path } => {
+        gone.insert(path.clone());
+        added.remove(path);
+      }
+      _ => {}
+    }
+    plan.push(action);
+  }
+
+  Ok(plan)
+}
+
+fn plan_undo(
+  step: &JournalStep,
+  exists: &dyn Fn(&Path) -> bool,
+) -> Result<Option<PatchAction>, CultivationError> {
+  match step {
+    JournalStep::Copied { path, sha256 } => {
+      if !exists(path) {
+        return Ok(None);
+      }
+      if !hash_matches(path, sha256)? {
+        return Err(CultivationError::Patch(format!(
+          "{:?} was changed since we patched it, so it was not removed",
+          path
+        )));
+      }
+
+      Ok(Some(PatchAction::Delete { path: path.clone() }))
+    }
+    JournalStep::Renamed { from, to, sha256 } => {
+      if exists(from) {
+        // Never renamed, or already put back
+        if !exists(to) {
+          return Ok(None);
+        }
+
+        warn!(
+          "Both {:?} and {:?} exist, leaving them as they are",
+          from, to
+        );
+        return Err(CultivationError::Patch(format!(
+          "{:?} is back, but so is {:?}. Keep whichever is the original and delete the other.",
+          from, to
+        )));
+      }
+      if !exists(to) {
+        return Err(CultivationError::Patch(format!(
+          "The original {:?} is missing, it was moved to {:?} but that's gone too",
+          from, to
+        )));
+      }
+      if !hash_matches(to, sha256)? {
+        return Err(CultivationError::Patch(format!(
+          "{:?} is not the file we moved there, so it was not put back",
+          to
+        )));
+      }
+
+      Ok(Some(PatchAction::Rename {
+        from: to.clone(),
+        to: from.clone(),
+      }))
+    }
+  }
+}
+
+fn apply_undo(action: &PatchAction) -> Result<(), CultivationError> {
+  match action {
+    PatchAction::Rename { from, to } => fs::rename(from, to)?,
+    PatchAction::Delete { path } => fs::remove_file(path)?,
+    // Undoing never copies
+    PatchAction::Copy { .. } | PatchAction::Keep { .. } | PatchAction::KeepMoved { .. } => {}
+  }
+
+  Ok(())
+}
+
+fn hash_matches(path: &Path, sha256: &str) -> Result<bool, CultivationError> {
+  Ok(file_helpers::sha256_file(path)?.eq_ignore_ascii_case(sha256))
+}
+
+/**
+ * Run on startup: if Cultivation quit without unpatching, put the original files back now.
+ * A journal of another Cultivation that's still running is left alone, its game may be running.
+ */
+pub fn recover() {
+  match load() {
+    Ok(journal) if journal.steps.is_empty() => {}
+    Ok(PatchJournal {
+      owner_pid: Some(pid),
+      ..
+    }) if is_running_elsewhere(pid) => {
+      info!(
+        "The game was patched by another Cultivation that's still running (pid {}), leaving it",
+        pid
+      );
+    }
+    Ok(_) => {
+      warn!("Found an unfinished patch from a previous run, restoring the game files");
+      if let Err(e) = restore() {
+        warn!(
+          "Could not restore the game files, use repair to retry: {}",
+          e
+        );
+      }
+    }
+    Err(e) => warn!("Could not read the patch journal: {}", e),
+  }
+}
+
+fn is_running_elsewhere(pid: u32) -> bool {
+  pid != std::process::id() && System::new().refresh_process(Pid::from(pid as usize))
+}
+
+#[tauri::command]
+pub async fn repair_patch() -> Result<Vec<String>, CultivationError> {
+  let _lock = patch::PATCH_LOCK.lock().await;
+
+  restore()
+}
@@ -0,0 +1,175 @@
+use crate::error::CultivationError;
+use crate::file_helpers;
+use crate::game_version::{self, GameVersion};
+use crate::games;
+use crate::system_helpers;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+// Bump when the manifest format changes in a way older launchers can't read
+const MANIFEST_VERSION: u64 = 1;
+
+/**
+ * patch/manifest.json (or the game's own, see games::Game), listing which patch to use for which
+ * game version.
+ * The first matching entry wins, so more specific ranges go first.
+ */
+#[derive(Deserialize, Debug)]
+pub struct PatchManifest {
+  pub manifest_version: u64,
+  pub patches: Vec<PatchEntry>,
+  #[serde(default)]
+  pub foreign_patches: Vec<ForeignPatch>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PatchEntry {
+  // Both inclusive, unset means unbounded
+  pub min_version: Option<GameVersion>,
+  pub max_version: Option<GameVersion>,
+  // "windows" or "linux", unset means any
+  pub platform: Option<String>,
+  // File in the patch directory
+  pub file: String,
+  // File name it's copied to in the game directory
+  pub target: String,
+  // The file the patch replaces, if the game ships one under the target name. Unset means
+  // whatever is there is taken to be the original, and moved aside like any other.
+  pub original_sha256: Option<String>,
+  pub patch_sha256: String,
+}
+
+// A patch other launchers apply, so we don't mistake it for a file of the game
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForeignPatch {
+  // Whose patch it is, eg. the launcher's name
+  pub name: String,
+  // "windows" or "linux", unset means any
+  pub platform: Option<String>,
+  pub target: String,
+  pub sha256: String,
+}
+
+impl ForeignPatch {
+  // A patch at `path` that's not in the manifest, told apart by the original it moved aside
+  pub fn unlisted(target: &str, path: &Path) -> Result<Self, CultivationError> {
+    Ok(ForeignPatch {
+      name: "another launcher".to_string(),
+      platform: None,
+      target: target.to_string(),
+      sha256: file_helpers::sha256_file(path)?,
+    })
+  }
+}
+
+impl PatchManifest {
+  pub fn find(&self, version: GameVersion) -> Option<&PatchEntry> {
+    self
+      .patches
+      .iter()
+      .find(|entry| entry.matches(version, std::env::consts::OS))
+  }
+
+  pub fn find_foreign(&self, target: &str, sha256: &str) -> Option<&ForeignPatch> {
+    self.foreign_patches.iter().find(|foreign| {
+      foreign.target.eq_ignore_ascii_case(target)
+        && foreign.sha256.eq_ignore_ascii_case(sha256)
+        && foreign
+          .platform
+          .as_deref()
+          .map_or(true, |p| p == std::env::consts::OS)
+    })
+  }
+}
+
+impl PatchEntry {
+  pub fn matches(&self, version: GameVersion, platform: &str) -> bool {
+    self.min_version.map_or(true, |min| version >= min)
+      && self.max_version.map_or(true, |max| version <= max)
+      && self.platform.as_deref().map_or(true, |p| p == platform)
+  }
+
+  pub fn patch_path(&self) -> PathBuf {
+    patch_dir().join(&self.file)
+  }
+
+  // Makes sure the patch we're about to copy is the one the manifest describes
+  pub fn verify_patch_file(&self) -> Result<(), CultivationError> {
+    let actual = file_helpers::sha256_file(self.patch_path())?;
+    if !actual.eq_ignore_ascii_case(&self.patch_sha256) {
+      return Err(CultivationError::Patch(format!(
+        "{} does not match the manifest (expected {}, got {}). Try reinstalling Cultivation.",
+        self.file, self.patch_sha256, actual
+      )));
+    }
+
+    Ok(())
+  }
+
+  // Whether `path` is the original file this patch was made for
+  pub fn is_original(&self, path: &Path) -> Result<bool, CultivationError> {
+    let Some(expected) = &self.original_sha256 else {
+      return Err(CultivationError::Patch(format!(
+        "The patch manifest has no original_sha256 for {} as {}, so the game's {} can't be \
+         checked and was left alone.",
+        self.file, self.target, self.target
+      )));
+    };
+
+    Ok(file_helpers::sha256_file(path)?.eq_ignore_ascii_case(expected))
+  }
+
+  pub fn is_patch(&self, path: &Path) -> Result<bool, CultivationError> {
+    Ok(file_helpers::sha256_file(path)?.eq_ignore_ascii_case(&self.patch_sha256))
+  }
+}
+
+pub fn patch_dir() -> PathBuf {
+  PathBuf::from(system_helpers::install_location()).join("patch")
+}
+
+// The manifest for whichever game `game_exe` is
+pub fn load_manifest(game_exe: &Path) -> Result<PatchManifest, CultivationError> {
+  let game = games::for_exe(game_exe).unwrap_or(&games::GENSHIN);
+  let Some(file) = game.patch_manifest else {
+    return Err(CultivationError::Patch(format!(
+      "There are no patches for {}",
+      game.name
+    )));
+  };
+
+  let path = patch_dir().join(file);
+  let manifest: PatchManifest = serde_json::from_str(&fs::read_to_string(&path)?)
+    .map_err(|e| CultivationError::Patch(format!("{:?} could not be read: {}", path, e)))?;
+
+  if manifest.manifest_version > MANIFEST_VERSION {
+    return Err(CultivationError::Patch(format!(
+      "The patch manifest is version {}, but this Cultivation only understands up to {}",
+      manifest.manifest_version, MANIFEST_VERSION
+    )));
+  }
+
+  Ok(manifest)
+}
+
+// Picks the patch for the game at `game_exe`, based on its version. None if there's none for it.
+pub fn select_patch(game_exe: &Path) -> Result<Option<PatchEntry>, CultivationError> {
+  let version = game_version::detect_game_version(game_exe)?;
+  let Some(entry) = load_manifest(game_exe)?.find(version).cloned() else {
+    info!(
+      "There is no patch for game version {} on {}",
+      version,
+      std::env::consts::OS
+    );
+    return Ok(None);
+  };
+
+  info!(
+    "Using patch {} as {} for game version {}",
+    entry.file, entry.target, version
+  );
+  Ok(Some(entry))
+}
@@ -0,0 +1,76 @@
+use once_cell::sync::OnceCell;
+use std::path::PathBuf;
+use tracing::warn;
+
+// If this file exists next to the executable, everything is stored next to it as well
+const PORTABLE_MARKER: &str = "portable.txt";
+const PORTABLE_DATA_DIR: &str = "data";
+
+static DATA_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/**
+ * Where Cultivation keeps everything it writes: config, CA, themes, logs and downloads.
+ * Resolved once, from --data-dir, a portable marker beside the executable, or the OS data dir.
+ */
+pub fn data_dir() -> PathBuf {
+  DATA_DIR.get_or_init(resolve_data_dir).clone()
+}
+
+// Has to run before anything reads the data dir, later calls are ignored
+pub fn set_data_dir(path: PathBuf) {
+  if DATA_DIR.set(path).is_err() {
+    warn!("Data directory was already resolved, ignoring --data-dir");
+  }
+}
+
+/**
+ * Picks --data-dir out of the raw arguments.
+ * Logging and the config are set up before the arguments are parsed properly, so this can't wait.
+ */
+pub fn data_dir_from_args(args: &[String]) -> Option<PathBuf> {
+  let mut iter = args.iter();
+  let value = loop {
+    let arg = iter.next()?;
+    if arg == "--data-dir" {
+      break iter.next()?.clone();
+    }
+    if let Some(value) = arg.strip_prefix("--data-dir=") {
+      break value.to_string();
+    }
+  };
+
+  // The working directory is changed to the executable's later on
+  let path = PathBuf::from(value);
+  match std::env::current_dir() {
+    Ok(cwd) if path.is_relative() => Some(cwd.join(path)),
+    _ => Some(path),
+  }
+}
+
+pub fn is_portable() -> bool {
+  exe_dir().map_or(false, |dir| dir.join(PORTABLE_MARKER).exists())
+}
+
+fn resolve_data_dir() -> PathBuf {
+  // Tests never get to write into the real one
+  if cfg!(test) {
+    return std::env::temp_dir().join(format!("cultivation-data-{}", std::process::id()));
+  }
+
+  match exe_dir() {
+    Some(dir) if is_portable() => dir.join(PORTABLE_DATA_DIR),
+    _ => tauri::api::path::data_dir().unwrap().join("cultivation"),
+  }
+}
+
+fn exe_dir() -> Option<PathBuf> {
+  let mut path = std::env::current_exe().ok()?;
+  path.pop();
+
+  Some(path)
+}
+
+#[tauri::command]
+pub fn get_data_dir() -> String {
+  data_dir().to_string_lossy().to_string()
+}
@@ -0,0 +1,132 @@
+use crate::config;
+use crate::error::CultivationError;
+use crate::proxy;
+use crate::rsa_key::ProfileKeys;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use tracing::info;
+
+// A server we connect to regularly. Unset fields leave the current setting alone when activated.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ServerProfile {
+  pub host: String,
+  #[serde(default, deserialize_with = "config::deserialize_port")]
+  pub port: Option<u16>,
+  pub https: Option<bool>,
+  // Redirect the other anime games' domains as well, see ProxyHandler
+  pub redirect_more: Option<bool>,
+  // Extra domains to send to this server, eg. for a server that also handles its own SDK
+  pub redirect_domains: Option<Vec<String>>,
+  pub patch_rsa: Option<bool>,
+  pub launch_args: Option<String>,
+  // The server's own public keys, see rsa_key
+  pub rsa_keys: Option<ProfileKeys>,
+}
+
+impl ServerProfile {
+  pub fn server_url(&self) -> String {
+    let scheme = if self.https.unwrap_or(false) {
+      "https"
+    } else {
+      "http"
+    };
+
+    format!("{}://{}:{}", scheme, self.host, self.port.unwrap_or(443))
+  }
+
+  // The top level config keys this profile sets, which is what the rest of the launcher reads
+  fn config_changes(&self, name: &str) -> Map<String, Value> {
+    let mut changes = Map::new();
+    changes.insert("active_profile".to_string(), json!(name));
+    changes.insert("last_ip".to_string(), json!(self.host));
+
+    if let Some(port) = self.port {
+      changes.insert("last_port".to_string(), json!(port));
+    }
+    if let Some(https) = self.https {
+      changes.insert("https_enabled".to_string(), json!(https));
+    }
+    if let Some(redirect_more) = self.redirect_more {
+      changes.insert("redirect_more".to_string(), json!(redirect_more));
+    }
+    // Always set, another server's domains must not be sent to this one
+    changes.insert(
+      "redirect_domains".to_string(),
+      json!(self.redirect_domains.clone().unwrap_or_default()),
+    );
+    if let Some(patch_rsa) = self.patch_rsa {
+      changes.insert("patch_rsa".to_string(), json!(patch_rsa));
+    }
+    if let Some(launch_args) = &self.launch_args {
+      changes.insert("launch_args".to_string(), json!(launch_args));
+    }
+
+    changes
+  }
+}
+
+#[derive(Serialize)]
+pub struct ProfileList {
+  pub active: Option<String>,
+  pub profiles: BTreeMap<String, ServerProfile>,
+}
+
+#[tauri::command]
+pub fn list_profiles() -> Result<ProfileList, CultivationError> {
+  let config = config::load_config()?;
+
+  Ok(ProfileList {
+    active: config.active_profile,
+    profiles: config.profiles.unwrap_or_default(),
+  })
+}
+
+/**
+ * Copies the profile's settings into the configuration and points the proxy at its server.
+ * Returns the activated profile.
+ */
+#[tauri::command]
+pub fn activate_profile(name: String) -> Result<ServerProfile, CultivationError> {
+  let profile = find_profile(&name)?;
+
+  config::update_config(profile.config_changes(&name))?;
+  // A profile picked with --profile would otherwise keep shadowing this one
+  config::set_session_overrides(Map::new());
+  proxy::set_proxy_addr(profile.server_url());
+
+  info!("Activated profile {}", name);
+  Ok(profile)
+}
+
+/**
+ * Like activate_profile, but only until Cultivation exits: the profile's settings are layered
+ * over configuration.json instead of being written to it. Used by --profile.
+ */
+pub fn use_profile(name: String) -> Result<ServerProfile, CultivationError> {
+  let profile = find_profile(&name)?;
+
+  config::set_session_overrides(profile.config_changes(&name));
+  proxy::set_proxy_addr(profile.server_url());
+
+  info!("Using profile {} for this session", name);
+  Ok(profile)
+}
+
+fn find_profile(name: &str) -> Result<ServerProfile, CultivationError> {
+  let profile = config::load_config()?
+    .profiles
+    .unwrap_or_default()
+    .remove(name)
+    .ok_or_else(|| CultivationError::Config(format!("There is no profile named '{}'", name)))?;
+
+  if profile.host.is_empty() {
+    return Err(CultivationError::Config(format!(
+      "Profile '{}' has no host",
+      name
+    )));
+  }
+
+  Ok(profile)
+}
@@ -3,7 +3,10 @@
  * https://github.com/omjadas/hudsucker/blob/main/examples/log.rs
  */
 
-use crate::config::get_config;
+use crate::config::shared_config;
+use crate::error::CultivationError;
+use crate::games;
+use crate::paths;
 
 use once_cell::sync::Lazy;
 use std::{path::PathBuf, str::FromStr, sync::Mutex};
@@ -21,7 +24,8 @@ use std::net::SocketAddr;
 use std::path::Path;
 
 use rustls_pemfile as pemfile;
-use tauri::{api::path::data_dir, http::Uri};
+use tauri::http::Uri;
+use tracing::{info, warn};
 
 #[cfg(windows)]
 use registry::{Data, Hive, Security};
@@ -42,6 +46,14 @@ async fn shutdown_signal() {
 // Global ver for getting server address.
 static SERVER: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new("http://localhost:443".to_string()));
 static REDIRECT_MORE: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+static CONNECTED: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+
+#[derive(serde::Serialize)]
+pub struct ProxyStatus {
+  pub server: String,
+  pub redirect_more: bool,
+  pub connected: bool,
+}
 
 #[derive(Clone)]
 struct ProxyHandler;
@@ -55,7 +67,7 @@ pub fn set_proxy_addr(addr: String) {
     *SERVER.lock().unwrap() = addr;
   }
 
-  println!("Set server to {}", SERVER.lock().unwrap());
+  info!("Set server to {}", SERVER.lock().unwrap());
 }
 
 #[tauri::command]
@@ -63,6 +75,18 @@ pub fn set_redirect_more() {
   *REDIRECT_MORE.lock().unwrap() = true;
 }
 
+pub fn set_connected(connected: bool) {
+  *CONNECTED.lock().unwrap() = connected;
+}
+
+pub fn proxy_status() -> ProxyStatus {
+  ProxyStatus {
+    server: SERVER.lock().unwrap().clone(),
+    redirect_more: *REDIRECT_MORE.lock().unwrap() || shared_config().redirect_more.unwrap_or(false),
+    connected: *CONNECTED.lock().unwrap(),
+  }
+}
+
 #[async_trait]
 impl HttpHandler for ProxyHandler {
   async fn handle_request(
@@ -72,93 +96,24 @@ impl HttpHandler for ProxyHandler {
   ) -> RequestOrResponse {
     let uri = req.uri().to_string();
 
-    let mut more = get_config().redirect_more;
-
-    if *REDIRECT_MORE.lock().unwrap() {
-      more = Some(true);
-    }
-
-    match more {
-      Some(true) => {
-        if uri.contains("hoyoverse.com")
-          || uri.contains("mihoyo.com")
-          || uri.contains("yuanshen.com")
-          || uri.contains("starrails.com")
-          || uri.contains("bhsr.com")
-          || uri.contains("bh3.com")
-          || uri.contains("honkaiimpact3.com")
-          || uri.contains("zenlesszonezero.com")
-        {
-          // Handle CONNECTs
-          if req.method().as_str() == "CONNECT" {
-            let builder = Response::builder()
-              .header("DecryptEndpoint", "Created")
-              .status(StatusCode::OK);
-            let res = builder.body(()).unwrap();
-
-            // Respond to CONNECT
-            *res.body()
-          } else {
-            let uri_path_and_query = req.uri().path_and_query().unwrap().as_str();
-            // Create new URI.
-            let new_uri =
-              Uri::from_str(format!("{}{}", SERVER.lock().unwrap(), uri_path_and_query).as_str())
-                .unwrap();
-            // Set request URI to the new one.
-            *req.uri_mut() = new_uri;
-          }
-        }
-      }
-      Some(false) => {
-        if uri.contains("hoyoverse.com")
-          || uri.contains("mihoyo.com")
-          || uri.contains("yuanshen.com")
-        {
-          // Handle CONNECTs
-          if req.method().as_str() == "CONNECT" {
-            let builder = Response::builder()
-              .header("DecryptEndpoint", "Created")
-              .status(StatusCode::OK);
-            let res = builder.body(()).unwrap();
-
-            // Respond to CONNECT
-            *res.body()
-          } else {
-            let uri_path_and_query = req.uri().path_and_query().unwrap().as_str();
-            // Create new URI.
-            let new_uri =
-              Uri::from_str(format!("{}{}", SERVER.lock().unwrap(), uri_path_and_query).as_str())
-                .unwrap();
-            // Set request URI to the new one.
-            *req.uri_mut() = new_uri;
-          }
-        }
-      }
-      // Use default as fallback
-      None => {
-        if uri.contains("hoyoverse.com")
-          || uri.contains("mihoyo.com")
-          || uri.contains("yuanshen.com")
-        {
-          // Handle CONNECTs
-          if req.method().as_str() == "CONNECT" {
-            let builder = Response::builder()
-              .header("DecryptEndpoint", "Created")
-              .status(StatusCode::OK);
-            let res = builder.body(()).unwrap();
-
-            // Respond to CONNECT
-            *res.body()
-          } else {
-            let uri_path_and_query = req.uri().path_and_query().unwrap().as_str();
-            // Create new URI.
-            let new_uri =
-              Uri::from_str(format!("{}{}", SERVER.lock().unwrap(), uri_path_and_query).as_str())
-                .unwrap();
-            // Set request URI to the new one.
-            *req.uri_mut() = new_uri;
-          }
-        }
+    if should_redirect(&uri) {
+      // Handle CONNECTs
+      if req.method().as_str() == "CONNECT" {
+        let builder = Response::builder()
+          .header("DecryptEndpoint", "Created")
+          .status(StatusCode::OK);
+        let res = builder.body(()).unwrap();
+
+        // Respond to CONNECT
+        *res.body()
+      } else {
+        let uri_path_and_query = req.uri().path_and_query().unwrap().as_str();
+        // Create new URI.
+        let new_uri =
+          Uri::from_str(format!("{}{}", SERVER.lock().unwrap(), uri_path_and_query).as_str())
+            .unwrap();
+        // Set request URI to the new one.
+        *req.uri_mut() = new_uri;
       }
     }
 
@@ -173,36 +128,33 @@ impl HttpHandler for ProxyHandler {
     response
   }
 
-  async fn should_intercept(&mut self, _ctx: &HttpContext, _req: &Request<Body>) -> bool {
-    let uri = _req.uri().to_string();
-
-    let more = get_config().redirect_more;
-
-    match more {
-      Some(true) => {
-        uri.contains("hoyoverse.com")
-          || uri.contains("mihoyo.com")
-          || uri.contains("yuanshen.com")
-          || uri.contains("starrails.com")
-          || uri.contains("bhsr.com")
-          || uri.contains("bh3.com")
-          || uri.contains("honkaiimpact3.com")
-          || uri.contains("zenlesszonezero.com")
-      }
-      Some(false) => {
-        uri.contains("hoyoverse.com") || uri.contains("mihoyo.com") || uri.contains("yuanshen.com")
-      }
-      None => {
-        uri.contains("hoyoverse.com") || uri.contains("mihoyo.com") || uri.contains("yuanshen.com")
-      }
-    }
+  async fn should_intercept(&mut self, _ctx: &HttpContext, req: &Request<Body>) -> bool {
+    should_redirect(&req.uri().to_string())
   }
 }
 
+// The current game's domains, or every game's with redirect_more, and the profile's own
+fn should_redirect(uri: &str) -> bool {
+  let config = shared_config();
+  let more = *REDIRECT_MORE.lock().unwrap() || config.redirect_more.unwrap_or(false);
+
+  games::redirect_domains(more)
+    .iter()
+    .any(|domain| uri.contains(domain))
+    || config
+      .redirect_domains
+      .iter()
+      .flatten()
+      .any(|domain| !domain.is_empty() && uri.contains(domain.as_str()))
+}
+
 /**
  * Starts an HTTP(S) proxy server.
  */
-pub async fn create_proxy(proxy_port: u16, certificate_path: String) {
+pub async fn create_proxy(
+  proxy_port: u16,
+  certificate_path: String,
+) -> Result<(), CultivationError> {
   let cert_path = PathBuf::from(certificate_path);
   let pk_path = cert_path.join("private.key");
   let ca_path = cert_path.join("cert.crt");
@@ -212,10 +164,10 @@ pub async fn create_proxy(proxy_port: u16, certificate_path: String) {
     // Try regenerating the CA stuff and read it again. If that doesn't work, quit.
     Ok(b) => b,
     Err(e) => {
-      println!("Encountered {}. Regenerating CA cert and retrying...", e);
-      generate_ca_files(&data_dir().unwrap().join("cultivation"));
+      warn!("Encountered {}. Regenerating CA cert and retrying...", e);
+      generate_ca_files(&paths::data_dir())?;
 
-      fs::read(&pk_path).expect("Could not read private key")
+      fs::read(&pk_path)?
     }
   };
 
@@ -223,29 +175,32 @@ pub async fn create_proxy(proxy_port: u16, certificate_path: String) {
     // Try regenerating the CA stuff and read it again. If that doesn't work, quit.
     Ok(b) => b,
     Err(e) => {
-      println!("Encountered {}. Regenerating CA cert and retrying...", e);
-      generate_ca_files(&data_dir().unwrap().join("cultivation"));
+      warn!("Encountered {}. Regenerating CA cert and retrying...", e);
+      generate_ca_files(&paths::data_dir())?;
 
-      fs::read(&ca_path).expect("Could not read certificate")
+      fs::read(&ca_path)?
     }
   };
 
   // Parse the private key and certificate.
   let private_key = rustls::PrivateKey(
-    pemfile::pkcs8_private_keys(&mut private_key_bytes)
-      .expect("Failed to parse private key")
-      .remove(0),
+    pemfile::pkcs8_private_keys(&mut private_key_bytes)?
+      .into_iter()
+      .next()
+      .ok_or_else(|| CultivationError::Config("No private key found".to_string()))?,
   );
 
   let ca_cert = rustls::Certificate(
-    pemfile::certs(&mut ca_cert_bytes)
-      .expect("Failed to parse CA certificate")
-      .remove(0),
+    pemfile::certs(&mut ca_cert_bytes)?
+      .into_iter()
+      .next()
+      .ok_or_else(|| CultivationError::Config("No CA certificate found".to_string()))?,
   );
 
   // Create the certificate authority.
-  let authority = RcgenAuthority::new(private_key, ca_cert, 1_000)
-    .expect("Failed to create Certificate Authority");
+  let authority = RcgenAuthority::new(private_key, ca_cert, 1_000).map_err(|e| {
+    CultivationError::Config(format!("Failed to create Certificate Authority: {}", e))
+  })?;
 
   // Create an instance of the proxy.
   let proxy = ProxyBuilder::new()
@@ -257,13 +212,25 @@ pub async fn create_proxy(proxy_port: u16, certificate_path: String) {
 
   // Start the proxy.
   tokio::spawn(proxy.start(shutdown_signal()));
+
+  Ok(())
+}
+
+#[cfg(windows)]
+fn registry_err(e: impl std::fmt::Display) -> CultivationError {
+  CultivationError::Permission(format!("Could not update proxy settings: {}", e))
+}
+
+#[cfg(target_os = "linux")]
+fn aagl_config_err(e: impl std::fmt::Display) -> CultivationError {
+  CultivationError::Config(format!("Failed to read the AAGL config: {}", e))
 }
 
 /**
  * Connects to the local HTTP(S) proxy server.
  */
 #[cfg(windows)]
-pub fn connect_to_proxy(proxy_port: u16) {
+pub fn connect_to_proxy(proxy_port: u16) -> Result<(), CultivationError> {
   // Create 'ProxyServer' string.
   let server_string: String = format!(
     "http=127.0.0.1:{};https=127.0.0.1:{}",
@@ -277,20 +244,23 @@ pub fn connect_to_proxy(proxy_port: u16) {
       // Only write should be needed but too many cases of Culti not being able to read/write proxy settings
       Security::AllAccess,
     )
-    .unwrap();
+    .map_err(registry_err)?;
 
   // Set registry values.
   settings
     .set_value("ProxyServer", &Data::String(server_string.parse().unwrap()))
-    .unwrap();
-  settings.set_value("ProxyEnable", &Data::U32(1)).unwrap();
+    .map_err(registry_err)?;
+  settings
+    .set_value("ProxyEnable", &Data::U32(1))
+    .map_err(registry_err)?;
 
-  println!("Connected to the proxy.");
+  info!("Connected to the proxy.");
+  Ok(())
 }
 
 #[cfg(target_os = "linux")]
-pub fn connect_to_proxy(proxy_port: u16) {
-  let mut config = Config::get().unwrap();
+pub fn connect_to_proxy(proxy_port: u16) -> Result<(), CultivationError> {
+  let mut config = Config::get().map_err(aagl_config_err)?;
   let proxy_addr = format!("127.0.0.1:{}", proxy_port);
   if !config.game.environment.contains_key("http_proxy") {
     config
@@ -305,35 +275,40 @@ pub fn connect_to_proxy(proxy_port: u16) {
       .insert("https_proxy".to_string(), proxy_addr);
   }
   Config::update(config);
+  Ok(())
 }
 
-#[cfg(target_od = "macos")]
-pub fn connect_to_proxy(_proxy_port: u16) {
-  println!("No Mac support yet. Someone mail me a Macbook and I will do it B)")
+#[cfg(target_os = "macos")]
+pub fn connect_to_proxy(_proxy_port: u16) -> Result<(), CultivationError> {
+  // No Mac support yet. Someone mail me a Macbook and I will do it B)
+  Err(CultivationError::unsupported("Connecting to the proxy"))
 }
 
 /**
  * Disconnects from the local HTTP(S) proxy server.
  */
 #[cfg(windows)]
-pub fn disconnect_from_proxy() {
+pub fn disconnect_from_proxy() -> Result<(), CultivationError> {
   // Fetch the 'Internet Settings' registry key.
   let settings = Hive::CurrentUser
     .open(
       r"Software\Microsoft\Windows\CurrentVersion\Internet Settings",
       Security::AllAccess,
     )
-    .unwrap();
+    .map_err(registry_err)?;
 
   // Set registry values.
-  settings.set_value("ProxyEnable", &Data::U32(0)).unwrap();
+  settings
+    .set_value("ProxyEnable", &Data::U32(0))
+    .map_err(registry_err)?;
 
-  println!("Disconnected from proxy.");
+  info!("Disconnected from proxy.");
+  Ok(())
 }
 
 #[cfg(target_os = "linux")]
-pub fn disconnect_from_proxy() {
-  let mut config = Config::get().unwrap();
+pub fn disconnect_from_proxy() -> Result<(), CultivationError> {
+  let mut config = Config::get().map_err(aagl_config_err)?;
   if config.game.environment.contains_key("http_proxy") {
     config.game.environment.remove("http_proxy");
   }
@@ -341,10 +316,13 @@ pub fn disconnect_from_proxy() {
     config.game.environment.remove("https_proxy");
   }
   Config::update(config);
+  Ok(())
 }
 
 #[cfg(target_os = "macos")]
-pub fn disconnect_from_proxy() {}
+pub fn disconnect_from_proxy() -> Result<(), CultivationError> {
+  Ok(())
+}
 
 /*
  * Generates a private key and certificate used by the certificate authority.
@@ -352,7 +330,7 @@ pub fn disconnect_from_proxy() {}
  * Source: https://github.com/zu1k/good-mitm/raw/master/src/ca/gen.rs
  */
 #[tauri::command]
-pub fn generate_ca_files(path: &Path) {
+pub fn generate_ca_files(path: &Path) -> Result<(), CultivationError> {
   let mut params = CertificateParams::default();
   let mut details = DistinguishedName::new();
 
@@ -373,63 +351,48 @@ pub fn generate_ca_files(path: &Path) {
   ];
 
   // Create certificate.
-  let cert = Certificate::from_params(params).unwrap();
-  let cert_crt = cert.serialize_pem().unwrap();
+  let cert_err = |e: RcgenError| CultivationError::Config(format!("Failed to create CA: {}", e));
+  let cert = Certificate::from_params(params).map_err(cert_err)?;
+  let cert_crt = cert.serialize_pem().map_err(cert_err)?;
   let private_key = cert.serialize_private_key_pem();
 
   // Make certificate directory.
   let cert_dir = path.join("ca");
-  match fs::create_dir(&cert_dir) {
-    Ok(_) => {}
-    Err(e) => {
-      println!("{}", e);
-    }
-  };
+  fs::create_dir_all(&cert_dir)?;
 
   // Write the certificate to a file.
   let cert_path = cert_dir.join("cert.crt");
-  match fs::write(&cert_path, cert_crt) {
-    Ok(_) => println!("Wrote certificate to {}", cert_path.to_str().unwrap()),
-    Err(e) => println!(
-      "Error writing certificate to {}: {}",
-      cert_path.to_str().unwrap(),
-      e
-    ),
-  }
+  fs::write(&cert_path, cert_crt)?;
+  info!("Wrote certificate to {}", cert_path.to_str().unwrap());
 
   // Write the private key to a file.
   let private_key_path = cert_dir.join("private.key");
-  match fs::write(&private_key_path, private_key) {
-    Ok(_) => println!(
-      "Wrote private key to {}",
-      private_key_path.to_str().unwrap()
-    ),
-    Err(e) => println!(
-      "Error writing private key to {}: {}",
-      private_key_path.to_str().unwrap(),
-      e
-    ),
-  }
+  fs::write(&private_key_path, private_key)?;
+  info!(
+    "Wrote private key to {}",
+    private_key_path.to_str().unwrap()
+  );
 
   // Install certificate into the system's Root CA store.
-  install_ca_files(&cert_path);
+  install_ca_files(&cert_path)
 }
 
 /*
  * Attempts to install the certificate authority's certificate into the Root CA store.
  */
 #[cfg(windows)]
-pub fn install_ca_files(cert_path: &Path) {
+pub fn install_ca_files(cert_path: &Path) -> Result<(), CultivationError> {
   crate::system_helpers::run_command(
     "certutil",
     vec!["-user", "-addstore", "Root", cert_path.to_str().unwrap()],
     None,
-  );
-  println!("Installed certificate.");
+  )?;
+  info!("Installed certificate.");
+  Ok(())
 }
 
 #[cfg(target_os = "macos")]
-pub fn install_ca_files(cert_path: &Path) {
+pub fn install_ca_files(cert_path: &Path) -> Result<(), CultivationError> {
   crate::system_helpers::run_command(
     "security",
     vec![
@@ -442,12 +405,13 @@ pub fn install_ca_files(cert_path: &Path) {
       cert_path.to_str().unwrap(),
     ],
     None,
-  );
-  println!("Installed certificate.");
+  )?;
+  info!("Installed certificate.");
+  Ok(())
 }
 
 #[cfg(target_os = "linux")]
-pub fn install_ca_files(cert_path: &Path) {
+pub fn install_ca_files(cert_path: &Path) -> Result<(), CultivationError> {
   let platform = os_type::current_platform();
   use os_type::OSType::*;
   // TODO: Add more distros
@@ -460,7 +424,7 @@ pub fn install_ca_files(cert_path: &Path) {
       // We want to execute multiple commands, but we don't want multiple pkexec prompts
       // so we have to use a script
       let script = Path::new("/tmp/cultivation-inject-ca-cert.sh");
-      let mut scriptf = File::create(script).unwrap();
+      let mut scriptf = File::create(script)?;
       #[cfg(debug_assertions)]
       let setflags = "xe";
       #[cfg(not(debug_assertions))]
@@ -483,39 +447,41 @@ update-ca-certificates
         cert_path.to_str().unwrap(),
         usr_certs.to_str().unwrap(),
         usr_cert_path.to_str().unwrap()
-      )
-      .unwrap();
-      scriptf.flush().unwrap();
+      )?;
+      scriptf.flush()?;
       drop(scriptf);
-      let _ = Command::new("bash")
+      let installed = Command::new("bash")
         .arg(script)
         .as_root_gui()
         .spawn_its_fine_really("Unable to install certificate");
       if let Err(e) = fs::remove_file(script) {
-        println!("Unable to remove certificate install script: {}", e);
+        warn!("Unable to remove certificate install script: {}", e);
       };
+      installed?;
     }
     // RedHat-based
     //Redhat | CentOS |
     // Arch-based
     Arch | Manjaro => {
-      let _ = Command::new("trust")
+      Command::new("trust")
         .arg("anchor")
         .arg("--store")
         .arg(cert_path)
         .as_root_gui()
-        .spawn_its_fine_really("Unable to install certificate");
+        .spawn_its_fine_really("Unable to install certificate")?;
     }
     OSX => unreachable!(),
     _ => {
-      println!("Unsupported Linux distribution.");
-      return;
+      return Err(CultivationError::unsupported(
+        "Installing the certificate on this Linux distribution",
+      ));
     }
   }
-  println!("Installed certificate.");
+  info!("Installed certificate.");
+  Ok(())
 }
 
 #[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
-pub fn install_ca_files(_cert_path: &Path) {
-  println!("Certificate installation is not supported on this platform.");
+pub fn install_ca_files(_cert_path: &Path) -> Result<(), CultivationError> {
+  Err(CultivationError::unsupported("Certificate installation"))
 }
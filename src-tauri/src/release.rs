@@ -1,3 +1,5 @@
+use crate::error::CultivationError;
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Release {
   pub tag_name: String,
@@ -5,29 +7,35 @@ pub struct Release {
 }
 
 #[tauri::command]
-pub async fn get_latest_release() -> Release {
+pub async fn get_latest_release() -> Result<Release, CultivationError> {
   let url = "https://api.github.com/repos/Grasscutters/Cultivation/releases/latest";
   let client = reqwest::Client::new();
   let response = client
     .get(url)
     .header("User-Agent", "Cultivation")
     .send()
-    .await
-    .unwrap();
-  let text = response.text().await.unwrap();
+    .await?;
+  let text = response.text().await?;
 
   // This includes ip when github rate limits you, so avoid it for now to avoid leaks through screenshots
   //println!("Response: {}", text);
 
   // Parse "tag_name" from JSON
-  let json: serde_json::Value = serde_json::from_str(&text).unwrap();
-  let tag_name = json["tag_name"].as_str().unwrap();
+  let json: serde_json::Value = serde_json::from_str(&text)
+    .map_err(|e| CultivationError::Network(format!("Invalid release response: {}", e)))?;
+  let missing =
+    |field: &str| CultivationError::Network(format!("Release response has no {}", field));
+  let tag_name = json["tag_name"]
+    .as_str()
+    .ok_or_else(|| missing("tag_name"))?;
 
   // Parse "html_url"
-  let link = json["html_url"].as_str().unwrap();
+  let link = json["html_url"]
+    .as_str()
+    .ok_or_else(|| missing("html_url"))?;
 
-  Release {
+  Ok(Release {
     tag_name: tag_name.to_string(),
     link: link.to_string(),
-  }
+  })
 }
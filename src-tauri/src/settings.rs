@@ -0,0 +1,349 @@
+use crate::config;
+use crate::discovery;
+use crate::error::CultivationError;
+use crate::paths;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use zip::write::FileOptions;
+
+const CONFIG_ENTRY: &str = "configuration.json";
+const MANIFEST_ENTRY: &str = "manifest.json";
+const THEMES_ENTRY: &str = "themes/";
+const CA_ENTRY: &str = "ca/";
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+  pub theme: Option<String>,
+  pub ca_imported: bool,
+  // The archive had a CA, but ours was kept because replace_ca wasn't set
+  pub ca_kept: bool,
+  // The archive had hooks, which were left out. Ours are kept.
+  pub hooks_skipped: bool,
+  // Paths that didn't exist here, and what they were changed to
+  pub rewritten: BTreeMap<String, String>,
+  // Paths that didn't exist here and couldn't be found. They are cleared, so the user has to pick them.
+  pub unresolved: Vec<String>,
+}
+
+/**
+ * Packs configuration.json (including server profiles), the selected theme and optionally the CA
+ * into one archive, for sharing a setup with someone else.
+ * Returns the path of the written archive.
+ */
+#[tauri::command]
+pub fn export_settings(
+  path: Option<String>,
+  include_ca: Option<bool>,
+) -> Result<String, CultivationError> {
+  let archive_path = match path {
+    Some(path) => PathBuf::from(path),
+    None => default_archive_path(),
+  };
+  let data_dir = paths::data_dir();
+  let config = config::load_config()?;
+
+  let mut zip = zip::ZipWriter::new(File::create(&archive_path)?);
+  let options = FileOptions::default();
+
+  zip.start_file(CONFIG_ENTRY, options)?;
+  zip.write_all(&fs::read(config::config_path())?)?;
+
+  let theme = config.theme.as_deref().and_then(find_theme_dir);
+  if let Some(theme_dir) = &theme {
+    let folder = theme_dir.file_name().unwrap_or_default().to_string_lossy();
+    add_dir(&mut zip, theme_dir, &format!("{}{}/", THEMES_ENTRY, folder))?;
+  }
+
+  let include_ca = include_ca.unwrap_or(false);
+  if include_ca {
+    add_dir(&mut zip, &data_dir.join("ca"), CA_ENTRY)?;
+  }
+
+  zip.start_file(MANIFEST_ENTRY, options)?;
+  zip.write_all(
+    serde_json::to_string_pretty(&json!({
+      "cultivation_version": env!("CARGO_PKG_VERSION"),
+      "theme": config.theme,
+      "ca": include_ca,
+    }))?
+    .as_bytes(),
+  )?;
+
+  zip.finish()?;
+
+  info!("Exported settings to {:?}", archive_path);
+  Ok(archive_path.to_string_lossy().to_string())
+}
+
+/**
+ * Restores an archive made by export_settings, replacing the current configuration.
+ * Paths that don't exist on this machine are replaced with `path_overrides` if given, then with
+ * whatever we can find, and cleared otherwise (see ImportReport::unresolved).
+ * An existing CA is only replaced with the archive's if `replace_ca` is set, and hooks are never
+ * imported.
+ */
+#[tauri::command]
+pub fn import_settings(
+  path: String,
+  path_overrides: Option<Map<String, Value>>,
+  replace_ca: Option<bool>,
+) -> Result<ImportReport, CultivationError> {
+  let mut zip = zip::ZipArchive::new(File::open(&path)?)?;
+  let data_dir = paths::data_dir();
+  let mut report = ImportReport::default();
+
+  // Replacing the CA breaks every certificate the user already trusts, so it has to be asked for
+  let has_ca = zip.file_names().any(|name| name.starts_with(CA_ENTRY));
+  let local_ca = data_dir.join("ca");
+  let has_local_ca = local_ca.join("cert.crt").exists() || local_ca.join("private.key").exists();
+  let skip_ca = has_ca && has_local_ca && !replace_ca.unwrap_or(false);
+  if skip_ca {
+    warn!(
+      "Keeping the existing CA, the one in {} was not imported",
+      path
+    );
+    report.ca_kept = true;
+  }
+
+  let mut raw = String::new();
+  zip
+    .by_name(CONFIG_ENTRY)
+    .map_err(|_| {
+      CultivationError::Archive(format!(
+        "{} has no {}, is it a settings export?",
+        path, CONFIG_ENTRY
+      ))
+    })?
+    .read_to_string(&mut raw)?;
+  let Value::Object(mut imported) = serde_json::from_str(&raw)? else {
+    return Err(CultivationError::Config(format!(
+      "{} in {} is not a JSON object",
+      CONFIG_ENTRY, path
+    )));
+  };
+
+  // Hooks run commands on this machine, so they're never taken from someone else's archive
+  if imported
+    .remove("hooks")
+    .map_or(false, |hooks| !hooks.is_null())
+  {
+    warn!(
+      "Skipping the hooks in {}, they have to be set up here",
+      path
+    );
+    report.hooks_skipped = true;
+  }
+  if let Some(hooks) = config::load_file_config()
+    .ok()
+    .and_then(|config| config.hooks)
+  {
+    imported.insert("hooks".to_string(), json!(hooks));
+  }
+
+  // Anything ending in _path points at something installed on the machine that exported it
+  let path_overrides = path_overrides.unwrap_or_default();
+  let path_keys: Vec<String> = imported
+    .keys()
+    .filter(|key| key.ends_with("_path"))
+    .cloned()
+    .collect();
+  for key in &path_keys {
+    let Some(old) = imported
+      .get(key)
+      .and_then(Value::as_str)
+      .map(str::to_string)
+    else {
+      continue;
+    };
+    if old.is_empty() || Path::new(&old).exists() {
+      continue;
+    }
+
+    let replacement = path_overrides
+      .get(key)
+      .and_then(Value::as_str)
+      .map(str::to_string)
+      .or_else(|| detect_path(key));
+
+    match replacement {
+      Some(new) => {
+        report.rewritten.insert(key.clone(), new.clone());
+        imported.insert(key.clone(), json!(new));
+      }
+      None => {
+        report.unresolved.push(key.clone());
+        imported.insert(key.clone(), json!(""));
+      }
+    }
+  }
+
+  // Staged next to where they go, and only moved in once the config has been accepted
+  let staging = data_dir.join("import.tmp");
+  if staging.exists() {
+    fs::remove_dir_all(&staging)?;
+  }
+  let mut staged = Vec::new();
+  for i in 0..zip.len() {
+    let mut entry = zip.by_index(i)?;
+    let Some(name) = entry.enclosed_name().map(Path::to_path_buf) else {
+      warn!("Skipping unsafe path {} in {}", entry.name(), path);
+      continue;
+    };
+
+    let is_theme = name.starts_with(THEMES_ENTRY);
+    let is_ca = name.starts_with(CA_ENTRY);
+    if !(is_theme || is_ca) || (is_ca && skip_ca) {
+      continue;
+    }
+
+    if entry.is_dir() {
+      continue;
+    }
+    let target = staging.join(&name);
+    if let Some(parent) = target.parent() {
+      fs::create_dir_all(parent)?;
+    }
+
+    let mut out = File::create(&target)?;
+    std::io::copy(&mut entry, &mut out)?;
+    staged.push(name);
+    report.ca_imported |= is_ca;
+  }
+
+  // The exporter's flag says nothing about this machine, only about what's in ca/ now
+  let cert_generated = report.ca_imported
+    || (has_local_ca
+      && config::load_config()
+        .ok()
+        .and_then(|config| config.cert_generated)
+        .unwrap_or(false));
+  imported.insert("cert_generated".to_string(), json!(cert_generated));
+
+  report.theme = imported
+    .get("theme")
+    .and_then(Value::as_str)
+    .map(str::to_string);
+
+  if let Err(e) = config::replace_config(Value::Object(imported)) {
+    fs::remove_dir_all(&staging).ok();
+    return Err(e);
+  }
+
+  for name in &staged {
+    let target = data_dir.join(name);
+    if let Some(parent) = target.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::rename(staging.join(name), target)?;
+  }
+  if staging.exists() {
+    fs::remove_dir_all(&staging)?;
+  }
+
+  info!("Imported settings from {}", path);
+  Ok(report)
+}
+
+fn default_archive_path() -> PathBuf {
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+
+  paths::data_dir().join(format!("settings-{}.zip", timestamp))
+}
+
+// Themes are selected by the name in their index.json, which doesn't have to match the folder
+fn find_theme_dir(name: &str) -> Option<PathBuf> {
+  if name == "default" {
+    return None;
+  }
+
+  fs::read_dir(paths::data_dir().join("themes"))
+    .ok()?
+    .flatten()
+    .map(|entry| entry.path())
+    .find(|dir| {
+      fs::read_to_string(dir.join("index.json"))
+        .ok()
+        .and_then(|index| serde_json::from_str::<Value>(&index).ok())
+        .map_or(false, |index| index["name"] == name)
+    })
+}
+
+fn add_dir<W: Write + std::io::Seek>(
+  zip: &mut zip::ZipWriter<W>,
+  dir: &Path,
+  prefix: &str,
+) -> Result<(), CultivationError> {
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    let name = format!(
+      "{}{}",
+      prefix,
+      path.file_name().unwrap_or_default().to_string_lossy()
+    );
+
+    if path.is_dir() {
+      add_dir(zip, &path, &format!("{}/", name))?;
+    } else {
+      zip.start_file(name, FileOptions::default())?;
+      zip.write_all(&fs::read(&path)?)?;
+    }
+  }
+
+  Ok(())
+}
+
+// Only the paths we know a usual location for
+fn detect_path(key: &str) -> Option<String> {
+  let found = match key {
+    "java_path" => detect_java(),
+    "grasscutter_path" => detect_grasscutter(),
+    "game_install_path" => discovery::discover()
+      .into_iter()
+      .next()
+      .map(|install| install.path),
+    _ => None,
+  };
+
+  found.map(|path| path.to_string_lossy().to_string())
+}
+
+fn detect_java() -> Option<PathBuf> {
+  let exe = if cfg!(windows) { "java.exe" } else { "java" };
+
+  let java_home = std::env::var_os("JAVA_HOME").map(|home| PathBuf::from(home).join("bin"));
+  let path_dirs = std::env::var_os("PATH")
+    .map(|path| std::env::split_paths(&path).collect::<Vec<_>>())
+    .unwrap_or_default();
+
+  java_home
+    .into_iter()
+    .chain(path_dirs)
+    .map(|dir| dir.join(exe))
+    .find(|java| java.is_file())
+}
+
+// The downloads menu installs Grasscutter into the data dir
+fn detect_grasscutter() -> Option<PathBuf> {
+  fs::read_dir(paths::data_dir().join("grasscutter"))
+    .ok()?
+    .flatten()
+    .map(|entry| entry.path())
+    .find(|path| {
+      let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+      name.starts_with("grasscutter") && name.ends_with(".jar")
+    })
+}
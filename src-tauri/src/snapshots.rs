@@ -0,0 +1,461 @@
+use crate::config::{self, Configuration};
+use crate::error::CultivationError;
+use crate::file_helpers;
+use crate::patch_journal::{self, JournalStep};
+use crate::paths;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// Older snapshots are dropped, along with any stored copies only they used
+const MAX_SNAPSHOTS: usize = 30;
+
+// Pruning must not remove a copy another snapshot is in the middle of storing
+static SNAPSHOT_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotFile {
+  pub path: PathBuf,
+  // None if the file didn't exist yet, rolling back deletes it again
+  pub sha256: Option<String>,
+}
+
+/**
+ * The files a single change was about to touch, as they were before it.
+ * Their contents are stored once per hash under snapshots/objects, so taking the same snapshot
+ * again only costs hashing.
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+  pub id: String,
+  // What was about to change, eg. "Extract GIMI-3dmigoto.zip"
+  pub reason: String,
+  pub created: u64,
+  pub files: Vec<SnapshotFile>,
+}
+
+impl Snapshot {
+  fn new(reason: impl Into<String>) -> Self {
+    let created = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_millis() as u64)
+      .unwrap_or_default();
+
+    Snapshot {
+      id: created.to_string(),
+      reason: reason.into(),
+      created: created / 1000,
+      files: vec![],
+    }
+  }
+
+  // Stores the current contents of `path`, unless it was captured already
+  fn capture(&mut self, path: &Path) -> Result<(), CultivationError> {
+    if self.files.iter().any(|file| file.path == path) || path.is_dir() {
+      return Ok(());
+    }
+
+    let sha256 = if path.is_file() {
+      Some(store_object(path)?)
+    } else {
+      None
+    };
+
+    self.files.push(SnapshotFile {
+      path: path.to_path_buf(),
+      sha256,
+    });
+    Ok(())
+  }
+
+  // Nothing is written if no files were captured
+  fn save(mut self) -> Result<(), CultivationError> {
+    if self.files.is_empty() {
+      return Ok(());
+    }
+
+    let dir = snapshots_dir();
+    fs::create_dir_all(&dir)?;
+
+    // Two snapshots in the same millisecond
+    while dir.join(format!("{}.json", self.id)).exists() {
+      self.id = format!("{}", self.id.parse::<u64>().unwrap_or_default() + 1);
+    }
+
+    fs::write(
+      dir.join(format!("{}.json", self.id)),
+      serde_json::to_string_pretty(&self)?,
+    )?;
+    info!(
+      "Took snapshot {} of {} files: {}",
+      self.id,
+      self.files.len(),
+      self.reason
+    );
+
+    if let Err(e) = prune() {
+      warn!("Failed to remove old snapshots: {}", e);
+    }
+    Ok(())
+  }
+}
+
+/**
+ * Captures `paths` before they are changed. Called by everything that writes to the game or mod
+ * directories, so the change can be rolled back with rollback_snapshot.
+ */
+pub fn take<P: AsRef<Path>>(
+  reason: impl Into<String>,
+  paths: impl IntoIterator<Item = P>,
+) -> Result<(), CultivationError> {
+  let _lock = SNAPSHOT_LOCK.lock().unwrap();
+
+  let mut snapshot = Snapshot::new(reason);
+  for path in paths {
+    snapshot.capture(path.as_ref())?;
+  }
+
+  snapshot.save()
+}
+
+/**
+ * Whether changes under `dir` are worth a snapshot: it's in the folder of a game, or of 3DMigoto
+ * where the mods go. Downloads of Grasscutter and the like are not.
+ */
+pub fn covers(dir: &Path) -> bool {
+  covered_by(dir, &config::get_config())
+}
+
+fn covered_by(dir: &Path, config: &Configuration) -> bool {
+  let games = config.games.iter().flat_map(|games| games.values());
+  let migoto = config.extra.get("migoto_path").and_then(Value::as_str);
+
+  let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+  config
+    .game_install_path
+    .iter()
+    .map(String::as_str)
+    .chain(games.map(String::as_str))
+    .chain(migoto)
+    .filter_map(|exe| Path::new(exe).parent())
+    .filter(|root| !root.as_os_str().is_empty())
+    .any(|root| dir.starts_with(fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf())))
+}
+
+// Newest first
+#[tauri::command]
+pub fn list_snapshots() -> Result<Vec<Snapshot>, CultivationError> {
+  let dir = snapshots_dir();
+  if !dir.exists() {
+    return Ok(vec![]);
+  }
+
+  let mut snapshots = vec![];
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.extension().map_or(false, |ext| ext == "json") {
+      match load(&path) {
+        Ok(snapshot) => snapshots.push(snapshot),
+        Err(e) => warn!("Skipping snapshot {:?}: {}", path, e),
+      }
+    }
+  }
+
+  snapshots.sort_by_key(|snapshot| Reverse(snapshot.id.parse::<u64>().unwrap_or_default()));
+  Ok(snapshots)
+}
+
+/**
+ * Puts every file in snapshot `id` back the way it was, and deletes the ones that didn't exist.
+ * The files are snapshotted again first, so a rollback can be rolled back too.
+ * Returns a description of every change made.
+ */
+#[tauri::command]
+pub fn rollback_snapshot(id: String) -> Result<Vec<String>, CultivationError> {
+  let _lock = SNAPSHOT_LOCK.lock().unwrap();
+  let snapshot = load(&snapshot_path(&id)?)?;
+
+  // The journal would no longer match the game files, unpatching has to put those back
+  let journal = patch_journal::load()?;
+  for step in &journal.steps {
+    let path = match step {
+      JournalStep::Renamed { to, .. } => to,
+      JournalStep::Copied { path, .. } => path,
+    };
+    if snapshot.files.iter().any(|file| &file.path == path) {
+      return Err(CultivationError::Patch(format!(
+        "{:?} is still patched, unpatch the game before rolling back",
+        path
+      )));
+    }
+  }
+
+  // Check everything is there before changing anything
+  for file in &snapshot.files {
+    if let Some(sha256) = &file.sha256 {
+      if !object_path(sha256).is_file() {
+        return Err(CultivationError::Io(format!(
+          "The stored copy of {:?} is missing, this snapshot can't be rolled back",
+          file.path
+        )));
+      }
+    }
+  }
+
+  // Saved once the files are back, so pruning can't drop the snapshot being rolled back
+  let mut before = Snapshot::new(format!("Before rolling back: {}", snapshot.reason));
+  for file in &snapshot.files {
+    before.capture(&file.path)?;
+  }
+
+  let restored = restore(&snapshot);
+  before.save()?;
+  let restored = restored?;
+
+  info!(
+    "Rolled back snapshot {} ({}), {} files changed",
+    snapshot.id,
+    snapshot.reason,
+    restored.len()
+  );
+  Ok(restored)
+}
+
+fn restore(snapshot: &Snapshot) -> Result<Vec<String>, CultivationError> {
+  let mut restored = vec![];
+
+  for file in &snapshot.files {
+    match &file.sha256 {
+      Some(sha256) => {
+        if file.path.is_file()
+          && file_helpers::sha256_file(&file.path)?.eq_ignore_ascii_case(sha256)
+        {
+          continue;
+        }
+
+        if let Some(parent) = file.path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::copy(object_path(sha256), &file.path)?;
+        restored.push(format!("Restore {:?}", file.path));
+      }
+      None => {
+        if !file.path.is_file() {
+          continue;
+        }
+
+        fs::remove_file(&file.path)?;
+        restored.push(format!("Delete {:?}", file.path));
+      }
+    }
+  }
+
+  Ok(restored)
+}
+
+fn snapshots_dir() -> PathBuf {
+  paths::data_dir().join("snapshots")
+}
+
+fn objects_dir() -> PathBuf {
+  snapshots_dir().join("objects")
+}
+
+fn object_path(sha256: &str) -> PathBuf {
+  objects_dir().join(sha256.to_lowercase())
+}
+
+fn snapshot_path(id: &str) -> Result<PathBuf, CultivationError> {
+  if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+    return Err(CultivationError::Config(format!(
+      "'{}' is not a snapshot id",
+      id
+    )));
+  }
+
+  let path = snapshots_dir().join(format!("{}.json", id));
+  if !path.is_file() {
+    return Err(CultivationError::Config(format!(
+      "There is no snapshot {}",
+      id
+    )));
+  }
+
+  Ok(path)
+}
+
+fn load(path: &Path) -> Result<Snapshot, CultivationError> {
+  Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+// Copies the file into the store if it isn't there yet, returning its hash
+fn store_object(path: &Path) -> Result<String, CultivationError> {
+  let sha256 = file_helpers::sha256_file(path)?;
+  let object = object_path(&sha256);
+
+  if !object.exists() {
+    fs::create_dir_all(objects_dir())?;
+
+    // Never leave a partial copy under the hash's name
+    let tmp = object.with_extension("tmp");
+    fs::copy(path, &tmp)?;
+    fs::rename(&tmp, &object)?;
+  }
+
+  Ok(sha256)
+}
+
+fn prune() -> Result<(), CultivationError> {
+  let snapshots = list_snapshots()?;
+  if snapshots.len() <= MAX_SNAPSHOTS {
+    return Ok(());
+  }
+
+  for snapshot in &snapshots[MAX_SNAPSHOTS..] {
+    fs::remove_file(snapshots_dir().join(format!("{}.json", snapshot.id)))?;
+    info!("Removed old snapshot {} ({})", snapshot.id, snapshot.reason);
+  }
+
+  let in_use: HashSet<String> = snapshots[..MAX_SNAPSHOTS]
+    .iter()
+    .flat_map(|snapshot| snapshot.files.iter())
+    .filter_map(|file| file.sha256.as_ref().map(|sha256| sha256.to_lowercase()))
+    .collect();
+
+  for entry in fs::read_dir(objects_dir())? {
+    let path = entry?.path();
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    if !in_use.contains(&name) {
+      fs::remove_file(&path)?;
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+      "cultivation-snapshots-{}-{}",
+      name,
+      std::process::id()
+    ));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn rolls_back_to_what_was_captured() {
+    let dir = temp_dir("rollback");
+    let changed = dir.join("d3dx.ini");
+    let added = dir.join("Mods").join("new.ini");
+    fs::write(&changed, "before").unwrap();
+
+    let reason = format!("Extract test mod {:?}", dir);
+    take(&reason, [&changed, &added, &dir]).unwrap();
+    fs::write(&changed, "after").unwrap();
+    fs::create_dir_all(added.parent().unwrap()).unwrap();
+    fs::write(&added, "new").unwrap();
+
+    let snapshot = list_snapshots()
+      .unwrap()
+      .into_iter()
+      .find(|snapshot| snapshot.reason == reason)
+      .unwrap();
+    // The directory itself isn't a file to put back
+    assert_eq!(snapshot.files.len(), 2);
+    assert!(snapshot.files[1].sha256.is_none());
+
+    let restored = rollback_snapshot(snapshot.id.clone()).unwrap();
+    assert_eq!(restored.len(), 2);
+    assert_eq!(fs::read_to_string(&changed).unwrap(), "before");
+    assert!(!added.exists());
+
+    // Nothing left to change the second time
+    let before = list_snapshots()
+      .unwrap()
+      .into_iter()
+      .find(|other| other.reason == format!("Before rolling back: {}", reason))
+      .unwrap();
+    assert!(rollback_snapshot(snapshot.id).unwrap().is_empty());
+
+    // And the rollback can be undone
+    rollback_snapshot(before.id).unwrap();
+    assert_eq!(fs::read_to_string(&changed).unwrap(), "after");
+    assert_eq!(fs::read_to_string(&added).unwrap(), "new");
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn skips_empty_snapshots() {
+    let reason = "Nothing to capture";
+    take(reason, Vec::<PathBuf>::new()).unwrap();
+    assert!(!list_snapshots()
+      .unwrap()
+      .iter()
+      .any(|snapshot| snapshot.reason == reason));
+  }
+
+  #[test]
+  fn refuses_ids_that_are_not_snapshots() {
+    assert!(rollback_snapshot("../configuration".to_string()).is_err());
+    assert!(rollback_snapshot(String::new()).is_err());
+    assert!(rollback_snapshot("1".to_string()).is_err());
+  }
+
+  #[test]
+  fn only_covers_the_game_and_mod_folders() {
+    let dir = temp_dir("covers");
+    let game = dir.join("Genshin Impact Game");
+    let migoto = dir.join("3dmigoto");
+    let server = dir.join("Grasscutter");
+    for folder in [&game, &migoto, &server] {
+      fs::create_dir_all(folder.join("sub")).unwrap();
+    }
+
+    let mut config = Configuration {
+      game_install_path: Some(game.join("GenshinImpact.exe").to_string_lossy().to_string()),
+      ..Default::default()
+    };
+    config.extra.insert(
+      "migoto_path".to_string(),
+      Value::String(
+        migoto
+          .join("3DMigoto Loader.exe")
+          .to_string_lossy()
+          .to_string(),
+      ),
+    );
+
+    assert!(covered_by(&game, &config));
+    assert!(covered_by(&game.join("sub"), &config));
+    assert!(covered_by(&migoto.join("sub"), &config));
+    assert!(!covered_by(&server, &config));
+    assert!(!covered_by(&dir, &config));
+    // Only where it ends up counts
+    assert!(!covered_by(&game.join("..").join("Grasscutter"), &config));
+
+    // Not set up is not covered, rather than everything relative to the working directory
+    assert!(!covered_by(&game, &Configuration::default()));
+    let unset = Configuration {
+      game_install_path: Some(String::new()),
+      ..Default::default()
+    };
+    assert!(!covered_by(Path::new("sub"), &unset));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,10 +1,18 @@
+use crate::error::CultivationError;
+use crate::snapshots;
 use ini::Ini;
 use std::path::PathBuf;
 use std::process::Command;
+use tracing::{debug, info};
+
+#[cfg(any(windows, target_os = "linux"))]
+use crate::{game_version, games};
 
 #[cfg(windows)]
 use std::ffi::OsStr;
 #[cfg(windows)]
+use tracing::warn;
+#[cfg(windows)]
 use {
   registry::{Data, Hive, Security},
   windows_service::service::{ServiceAccess, ServiceState::Stopped},
@@ -22,13 +30,15 @@ use anime_launcher_sdk::{
 use std::{path::Path, process::Stdio, thread};
 #[cfg(target_os = "linux")]
 use term_detect::get_terminal;
+#[cfg(target_os = "linux")]
+use tracing::{error, warn};
 
 #[cfg(target_os = "linux")]
 fn guess_user_terminal() -> String {
   if let Ok(term) = get_terminal() {
     return term.0;
   }
-  eprintln!("Could not guess default terminal. Try setting the $TERMINAL environment variable.");
+  warn!("Could not guess default terminal. Try setting the $TERMINAL environment variable.");
   // If everything fails, default to xterm
   "xterm".to_string()
 }
@@ -85,142 +95,231 @@ impl InTerminalEmulator for Command {
 
 #[cfg(target_os = "linux")]
 pub trait SpawnItsFineReally {
-  fn spawn_its_fine_really(&mut self, msg: &str) -> anyhow::Result<()>;
+  fn spawn_its_fine_really(&mut self, msg: &str) -> Result<(), CultivationError>;
 }
 
 #[cfg(target_os = "linux")]
 impl SpawnItsFineReally for Command {
-  fn spawn_its_fine_really(&mut self, msg: &str) -> anyhow::Result<()> {
+  fn spawn_its_fine_really(&mut self, msg: &str) -> Result<(), CultivationError> {
     let res = self.status();
     let Ok(status) = res else {
       let error = res.unwrap_err();
-      println!("{}: {}", msg, &error);
+      error!("{}: {}", msg, &error);
       return Err(error.into());
     };
     if !status.success() {
-      println!("{}: {}", msg, status);
-      Err(anyhow::anyhow!("{}: {}", msg, status))
+      error!("{}: {}", msg, status);
+      Err(CultivationError::Io(format!("{}: {}", msg, status)))
     } else {
       Ok(())
     }
   }
 }
 
+/**
+ * Splits launch arguments on whitespace, so `-monitor "2"` or a quoted path with spaces stays one
+ * argument. Double or single quotes group, and the other kind is kept inside them. Backslashes are
+ * kept as they are, the game is a Windows program (on Linux too) and takes Windows paths.
+ */
+pub fn parse_launch_args(args: &str) -> Result<Vec<String>, CultivationError> {
+  let mut parsed = vec![];
+  let mut current: Option<String> = None;
+  let mut quote: Option<char> = None;
+
+  for c in args.chars() {
+    match quote {
+      Some(open) if c == open => quote = None,
+      Some(_) => current.get_or_insert_with(String::new).push(c),
+      None if c == '"' || c == '\'' => {
+        quote = Some(c);
+        // So "" is an empty argument rather than none
+        current.get_or_insert_with(String::new);
+      }
+      None if c.is_whitespace() => parsed.extend(current.take()),
+      None => current.get_or_insert_with(String::new).push(c),
+    }
+  }
+
+  if quote.is_some() {
+    return Err(CultivationError::Config(format!(
+      "Could not parse the launch arguments, is a quote left open? {}",
+      args
+    )));
+  }
+
+  parsed.extend(current);
+  Ok(parsed)
+}
+
+// Quotes `args` back into one command line, the way the program's CommandLineToArgvW will split it
+#[cfg(windows)]
+fn windows_command_line(args: &[String]) -> String {
+  let quoted: Vec<String> = args
+    .iter()
+    .map(|arg| {
+      if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return arg.clone();
+      }
+
+      // Backslashes are only special before a quote
+      let mut quoted = String::from('"');
+      let mut backslashes = 0;
+      for c in arg.chars() {
+        match c {
+          '\\' => backslashes += 1,
+          '"' => {
+            quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+            quoted.push('"');
+            backslashes = 0;
+          }
+          _ => {
+            quoted.push_str(&"\\".repeat(backslashes));
+            quoted.push(c);
+            backslashes = 0;
+          }
+        }
+      }
+      quoted.push_str(&"\\".repeat(backslashes * 2));
+      quoted.push('"');
+      quoted
+    })
+    .collect();
+
+  quoted.join(" ")
+}
+
+#[cfg(windows)]
 #[tauri::command]
-pub fn run_program(path: String, args: Option<String>) {
-  // Without unwrap_or, this can crash when UAC prompt is denied
-  match open::with(
-    format!("{} {}", path, args.unwrap_or_else(|| "".into())),
-    path.clone(),
-  ) {
-    Ok(_) => (),
-    Err(e) => println!("Failed to open program ({}): {}", &path, e),
-  };
+pub fn run_program(path: String, args: Option<String>) -> Result<(), CultivationError> {
+  let args = parse_launch_args(args.as_deref().unwrap_or_default())?;
+
+  // This can fail when UAC prompt is denied
+  open::with(windows_command_line(&args), path)?;
+  Ok(())
+}
+
+#[cfg(not(windows))]
+#[tauri::command]
+pub fn run_program(path: String, args: Option<String>) -> Result<(), CultivationError> {
+  let args = parse_launch_args(args.as_deref().unwrap_or_default())?;
+  let mut handler = Command::new(path).args(args).spawn()?;
+
+  // Prevent creation of zombie processes
+  std::thread::spawn(move || {
+    let _ = handler.wait();
+  });
+
+  Ok(())
 }
 
 #[cfg(target_os = "windows")]
 #[tauri::command]
-pub fn run_program_relative(path: String, args: Option<String>) {
+pub fn run_program_relative(path: String, args: Option<String>) -> Result<(), CultivationError> {
+  let args = parse_launch_args(args.as_deref().unwrap_or_default())?;
+
   // Save the current working directory
-  let cwd = std::env::current_dir().unwrap();
+  let cwd = std::env::current_dir()?;
 
   // Set the new working directory to the path before the executable
   let mut path_buf = std::path::PathBuf::from(&path);
   path_buf.pop();
 
   // Set new working directory
-  std::env::set_current_dir(&path_buf).unwrap();
+  std::env::set_current_dir(&path_buf)?;
 
-  // Without unwrap_or, this can crash when UAC prompt is denied
-  open::that(format!("{} {}", &path, args.unwrap_or_else(|| "".into()))).unwrap_or(());
+  // This can fail when UAC prompt is denied
+  let opened = open::with(windows_command_line(&args), path);
 
   // Restore the original working directory
-  std::env::set_current_dir(cwd).unwrap();
+  std::env::set_current_dir(cwd)?;
+
+  opened?;
+  Ok(())
 }
 
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub fn run_program_relative(path: String, args: Option<String>) {
+pub fn run_program_relative(path: String, args: Option<String>) -> Result<(), CultivationError> {
   // This program should not run as root
   run_un_elevated(path, args)
 }
 
 #[tauri::command]
-pub fn run_command(program: &str, args: Vec<&str>, relative: Option<bool>) {
-  let prog = program.to_string();
-  let args = args.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+pub fn run_command(
+  program: &str,
+  args: Vec<&str>,
+  relative: Option<bool>,
+) -> Result<(), CultivationError> {
+  let mut command = Command::new(program);
+  command.args(&args);
+
+  if relative.unwrap_or(false) {
+    // Set the working directory to the path before the executable
+    let mut path_buf = std::path::PathBuf::from(program);
+    path_buf.pop();
+
+    command.current_dir(path_buf);
+  }
 
   // Commands should not block (this is for the reshade injector mostly)
-  std::thread::spawn(move || {
-    // Save the current working directory
-    let cwd = std::env::current_dir().unwrap();
-
-    if relative.unwrap_or(false) {
-      // Set the new working directory to the path before the executable
-      let mut path_buf = std::path::PathBuf::from(&prog);
-      path_buf.pop();
-
-      // Set new working directory
-      std::env::set_current_dir(&path_buf).unwrap();
-    }
-
-    // Run the command
-    let mut command = Command::new(&prog);
-    command.args(&args);
-    command.spawn().unwrap();
-
-    // Restore the original working directory
-    std::env::set_current_dir(cwd).unwrap();
-  });
+  command.spawn()?;
+  Ok(())
 }
 
 #[tauri::command]
-pub fn run_jar(path: String, execute_in: String, java_path: String) {
+pub fn run_jar(
+  path: String,
+  execute_in: String,
+  java_path: String,
+) -> Result<(), CultivationError> {
   let command = if java_path.is_empty() {
     format!("java -jar \"{}\"", path)
   } else {
     format!("\"{}\" -jar \"{}\"", java_path, path)
   };
 
-  println!("Launching .jar with command: {}", &command);
+  info!("Launching .jar with command: {}", &command);
 
   // Open the program from the specified path.
   #[cfg(not(target_os = "linux"))]
-  match open::with(
+  open::with(
     format!("/k cd /D \"{}\" & {}", &execute_in, &command),
     "C:\\Windows\\System32\\cmd.exe",
-  ) {
-    Ok(_) => (),
-    Err(e) => println!("Failed to open jar ({} from {}): {}", &path, &execute_in, e),
-  };
+  )?;
   #[cfg(target_os = "linux")]
-  thread::spawn(move || {
-    match Command::new(guess_user_terminal())
+  {
+    let mut handler = Command::new(guess_user_terminal())
       .arg("-e")
       .arg(command)
-      .current_dir(execute_in.clone())
-      .spawn()
-    {
-      Ok(mut handler) => {
-        // Prevent creation of zombie processes
-        handler
-          .wait()
-          .expect("Grasscutter exited with non-zero exit code");
-      }
-      Err(e) => println!("Failed to open jar ({} from {}): {}", &path, &execute_in, e),
-    }
-  });
+      .current_dir(execute_in)
+      .spawn()?;
+
+    // Prevent creation of zombie processes
+    thread::spawn(move || {
+      let _ = handler.wait();
+    });
+  }
+
+  Ok(())
 }
 
 #[cfg(not(target_os = "linux"))]
 #[tauri::command]
-pub fn run_jar_root(_path: String, _execute_in: String, _java_path: String) {
-  panic!("Not implemented");
+pub fn run_jar_root(
+  _path: String,
+  _execute_in: String,
+  _java_path: String,
+) -> Result<(), CultivationError> {
+  Err(CultivationError::unsupported("Running Grasscutter as root"))
 }
 
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub fn run_jar_root(path: String, execute_in: String, java_path: String) {
+pub fn run_jar_root(
+  path: String,
+  execute_in: String,
+  java_path: String,
+) -> Result<(), CultivationError> {
   let mut command = if java_path.is_empty() {
     Command::new("java")
   } else {
@@ -228,43 +327,40 @@ pub fn run_jar_root(path: String, execute_in: String, java_path: String) {
   };
   command.arg("-jar").arg(&path).current_dir(&execute_in);
 
-  println!("Launching .jar with command: {}", strcmd(&command));
+  info!("Launching .jar with command: {}", strcmd(&command));
 
   // Open the program from the specified path.
+  let mut handler = command.as_root_gui().in_terminal().spawn()?;
+
+  // Prevent creation of zombie processes
   thread::spawn(move || {
-    match command.as_root_gui().in_terminal().spawn() {
-      Ok(mut handler) => {
-        // Prevent creation of zombie processes
-        handler
-          .wait()
-          .expect("Grasscutter exited with non-zero exit code");
-      }
-      Err(e) => println!("Failed to open jar ({} from {}): {}", &path, &execute_in, e),
-    }
+    let _ = handler.wait();
   });
+
+  Ok(())
 }
 
 #[cfg(target_os = "windows")]
 #[tauri::command]
-pub fn run_un_elevated(path: String, args: Option<String>) {
-  // Open the program non-elevated.
-  match open::with(
-    format!(
-      "cmd /min /C \"set __COMPAT_LAYER=RUNASINVOKER && start \"\" \"{}\"\" {}",
-      path,
-      args.unwrap_or_else(|| "".into())
-    ),
-    "C:\\Windows\\System32\\cmd.exe",
-  ) {
-    Ok(_) => (),
-    Err(e) => println!("Failed to open program ({}): {}", &path, e),
-  };
+pub fn run_un_elevated(path: String, args: Option<String>) -> Result<(), CultivationError> {
+  let args = parse_launch_args(args.as_deref().unwrap_or_default())?;
+
+  // Open the program non-elevated, the compatibility layer stops it from asking for admin
+  Command::new(path)
+    .args(args)
+    .env("__COMPAT_LAYER", "RUNASINVOKER")
+    .spawn()?;
+  Ok(())
 }
 
 #[cfg(target_os = "linux")]
-fn aagl_wine_run<P: AsRef<Path>>(path: P, args: Option<String>) -> Command {
-  let config = Config::get().unwrap();
-  let wine = config.get_selected_wine().unwrap().unwrap();
+fn aagl_wine_run<P: AsRef<Path>>(path: P, args: &[String]) -> Result<Command, CultivationError> {
+  let config = Config::get()
+    .map_err(|e| CultivationError::Config(format!("Failed to read the AAGL config: {}", e)))?;
+  let wine = config
+    .get_selected_wine()
+    .map_err(|e| CultivationError::Config(format!("Failed to get the selected wine: {}", e)))?
+    .ok_or_else(|| CultivationError::Config("No wine version is selected in AAGL".to_string()))?;
   let wine_run = wine
     .to_wine(
       config.components.path,
@@ -288,78 +384,80 @@ fn aagl_wine_run<P: AsRef<Path>>(path: P, args: Option<String>) -> Command {
     Proton(proton) => proton.wine().clone(),
   };
   let mut cmd = Command::new(&wined.binary);
-  cmd.arg(path.as_ref()).envs(wined.get_envs()).envs(env);
-  if let Some(args) = args {
-    let mut args: Vec<String> = args.split(' ').map(|x| x.to_string()).collect();
-    cmd.args(&mut args);
-  };
   cmd
+    .arg(path.as_ref())
+    .args(args)
+    .envs(wined.get_envs())
+    .envs(env);
+  Ok(cmd)
 }
 
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub fn run_un_elevated(path: String, args: Option<String>) {
+pub fn run_un_elevated(path: String, args: Option<String>) -> Result<(), CultivationError> {
   let path = Path::new(&path);
-  let exec_name = path.file_name().unwrap().to_str().unwrap();
-  if exec_name == ["Yuan", "Shen", ".exe"].join("").as_str()
-    || exec_name == ["Gen", "shin", "Impact", ".exe"].join("").as_str()
-  {
-    let game_thread = thread::spawn(|| {
-      'statechk: {
-        let state = LauncherState::get_from_config(|_| {});
-        let Ok(state) = state else {
-          println!("Failed to get state: {}", state.unwrap_err());
-          break 'statechk;
-        };
-        use anime_launcher_sdk::genshin::states::LauncherState::*;
-        match state {
-          FolderMigrationRequired { from, .. } => Err(format!(
-            "A folder migration is required ({:?} needs to be moved)",
-            from
-          )),
-          WineNotInstalled => Err("Wine is not installed".to_string()),
-          PrefixNotExists => Err("The Wine prefix does not exist".to_string()),
-          GameNotInstalled(_) => Err("The game is not installed".to_string()),
-          _ => Ok(()),
-        }
-        .expect("Can't launch game. Check the other launcher.");
+  let aagl_module = games::for_exe(path).and_then(|game| game.aagl_module);
+  if aagl_module == Some(games::AaglModule::Genshin) {
+    'statechk: {
+      let state = LauncherState::get_from_config(|_| {});
+      let Ok(state) = state else {
+        warn!("Failed to get state: {}", state.unwrap_err());
+        break 'statechk;
+      };
+      use anime_launcher_sdk::genshin::states::LauncherState::*;
+      let problem = match state {
+        FolderMigrationRequired { from, .. } => Some(format!(
+          "A folder migration is required ({:?} needs to be moved)",
+          from
+        )),
+        WineNotInstalled => Some("Wine is not installed".to_string()),
+        PrefixNotExists => Some("The Wine prefix does not exist".to_string()),
+        GameNotInstalled(_) => Some("The game is not installed".to_string()),
+        _ => None,
+      };
+      if let Some(problem) = problem {
+        return Err(CultivationError::Config(format!(
+          "Can't launch game: {}. Check the other launcher.",
+          problem
+        )));
       }
+    }
+    let game_thread = thread::spawn(|| {
       if let Err(e) = game::run() {
-        println!("An error occurred while running the game: {}", e);
+        error!("An error occurred while running the game: {}", e);
       }
     });
     {
       let mut game_thead_lock = AAGL_THREAD.lock().unwrap();
       game_thead_lock.replace(game_thread);
     }
-    return;
+    return Ok(());
   }
   // Run exe with wine
-  if path.extension().unwrap() == "exe" {
-    let path = path.to_owned().clone();
+  if path.extension().map_or(false, |ext| ext == "exe") {
+    let path = path.to_owned();
+    let args = parse_launch_args(args.as_deref().unwrap_or_default())?;
+    let mut cmd = aagl_wine_run(&path, &args)?;
+    cmd.current_dir(path.parent().unwrap_or(path.as_path()));
     thread::spawn(move || {
-      let _ = aagl_wine_run(&path, args)
-        .current_dir(path.parent().unwrap())
-        .in_terminal()
-        .spawn_its_fine_really(&format!(
-          "Failed to open program ({})",
-          path.to_str().unwrap()
-        ));
+      let _ = cmd.in_terminal().spawn_its_fine_really(&format!(
+        "Failed to open program ({})",
+        path.to_str().unwrap()
+      ));
     });
+    return Ok(());
   }
-  println!(
+  Err(CultivationError::PlatformUnsupported(format!(
     "Can't run {:?}. Running this type of file is not supported yet.",
     path
-  );
+  )))
 }
 
 #[tauri::command]
-pub fn open_in_browser(url: String) {
+pub fn open_in_browser(url: String) -> Result<(), CultivationError> {
   // Open the URL in the default browser.
-  match open::that(url) {
-    Ok(_) => (),
-    Err(e) => println!("Failed to open URL: {}", e),
-  };
+  open::that(url)?;
+  Ok(())
 }
 
 #[tauri::command]
@@ -391,22 +489,17 @@ pub fn install_location() -> String {
 }
 
 #[tauri::command]
-pub fn set_migoto_target(window: tauri::Window, migoto_path: String) -> bool {
+pub fn set_migoto_target(
+  window: tauri::Window,
+  migoto_path: String,
+) -> Result<(), CultivationError> {
   let mut migoto_pathbuf = PathBuf::from(migoto_path);
 
   migoto_pathbuf.pop();
   migoto_pathbuf.push("d3dx.ini");
 
-  let mut conf = match Ini::load_from_file(&migoto_pathbuf) {
-    Ok(c) => {
-      println!("Loaded migoto ini");
-      c
-    }
-    Err(e) => {
-      println!("Error loading migoto config: {}", e);
-      return false;
-    }
-  };
+  let mut conf = Ini::load_from_file(&migoto_pathbuf)?;
+  debug!("Loaded migoto ini");
 
   window.emit("migoto_set", &()).unwrap();
 
@@ -416,101 +509,88 @@ pub fn set_migoto_target(window: tauri::Window, migoto_path: String) -> bool {
     .set("target", "GenshinImpact.exe");
 
   // Write file
-  match conf.write_to_file(&migoto_pathbuf) {
-    Ok(_) => {
-      println!("Wrote config!");
-      true
-    }
-    Err(e) => {
-      println!("Error writing config: {}", e);
-      false
-    }
-  }
+  snapshots::take("Set 3DMigoto target", [&migoto_pathbuf])?;
+  conf.write_to_file(&migoto_pathbuf)?;
+  info!("Wrote config!");
+  Ok(())
 }
 
 #[tauri::command]
-pub fn set_migoto_delay(migoto_path: String) -> bool {
+pub fn set_migoto_delay(migoto_path: String) -> Result<(), CultivationError> {
   let mut migoto_pathbuf = PathBuf::from(migoto_path);
 
   migoto_pathbuf.pop();
   migoto_pathbuf.push("d3dx.ini");
 
-  let mut conf = match Ini::load_from_file(&migoto_pathbuf) {
-    Ok(c) => {
-      println!("Loaded migoto ini");
-      c
-    }
-    Err(e) => {
-      println!("Error loading migoto config: {}", e);
-      return false;
-    }
-  };
+  let mut conf = Ini::load_from_file(&migoto_pathbuf)?;
+  debug!("Loaded migoto ini");
 
   // Set options
   conf.with_section(Some("Loader")).set("delay", "20");
 
   // Write file
-  match conf.write_to_file(&migoto_pathbuf) {
-    Ok(_) => {
-      println!("Wrote delay!");
-      true
-    }
-    Err(e) => {
-      println!("Error writing delay: {}", e);
-      false
-    }
-  }
+  snapshots::take("Set 3DMigoto delay", [&migoto_pathbuf])?;
+  conf.write_to_file(&migoto_pathbuf)?;
+  info!("Wrote delay!");
+  Ok(())
 }
 
+// Wipes the login cached by the current game, see games::Executable
 #[cfg(windows)]
 #[tauri::command]
-pub fn wipe_registry(exec_name: String) {
-  // Fetch the 'Internet Settings' registry key.
-  let settings =
-    match Hive::CurrentUser.open(format!("Software\\miHoYo\\{}", exec_name), Security::Write) {
-      Ok(s) => s,
-      Err(e) => {
-        println!("Error getting registry setting: {}", e);
-        return;
-      }
-    };
+pub fn wipe_registry() -> Result<(), CultivationError> {
+  let game = games::current();
+  let exe = game.executable(&game_version::game_exe()?);
+  let Some(login_value) = exe.login_value else {
+    warn!(
+      "Not wiping the login, where {} keeps it is not known",
+      game.name
+    );
+    return Ok(());
+  };
+
+  // Fetch the game's registry key, it's only there once the game has run
+  let settings = match Hive::CurrentUser.open(exe.registry_key, Security::Write) {
+    Ok(settings) => settings,
+    Err(registry::key::Error::NotFound(..)) => {
+      info!("No login to wipe, {} does not exist", exe.registry_key);
+      return Ok(());
+    }
+    Err(e) => {
+      return Err(CultivationError::Permission(format!(
+        "Error getting registry setting: {}",
+        e
+      )))
+    }
+  };
 
   // Wipe login cache
-  match settings.set_value(
-    "MIHOYOSDK_ADL_PROD_OVERSEA_h1158948810",
-    &Data::String("".parse().unwrap()),
-  ) {
-    Ok(_) => (),
-    Err(e) => println!("Error wiping registry: {}", e),
-  }
+  settings
+    .set_value(login_value, &Data::String("".parse().unwrap()))
+    .map_err(|e| CultivationError::Permission(format!("Error wiping registry: {}", e)))
+}
+
+#[cfg(windows)]
+fn service_err(e: windows_service::Error) -> CultivationError {
+  CultivationError::Permission(format!("Service manager error: {}", e))
 }
 
 #[cfg(windows)]
 #[tauri::command]
-pub fn service_status(service: String) -> bool {
-  let manager = match ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT) {
-    Ok(manager) => manager,
-    Err(_e) => return false,
-  };
-  let my_service = match manager.open_service(service.clone(), ServiceAccess::QUERY_STATUS) {
-    Ok(my_service) => my_service,
-    Err(_e) => {
-      println!("{} service not found! Not installed?", service);
-      return false;
-    }
+pub fn service_status(service: String) -> Result<bool, CultivationError> {
+  let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+    .map_err(service_err)?;
+  let Ok(my_service) = manager.open_service(service.clone(), ServiceAccess::QUERY_STATUS) else {
+    info!("{} service not found! Not installed?", service);
+    return Ok(false);
   };
-  let status_result = my_service.query_status();
-  if let Ok(..) = status_result {
-    let status = status_result.unwrap();
-    println!("{} service status: {:?}", service, status.current_state);
-    if status.current_state == Stopped {
-      // Start the service if it is stopped
-      start_service(service);
-    }
-    true
-  } else {
-    false
+  let status = my_service.query_status().map_err(service_err)?;
+  info!("{} service status: {:?}", service, status.current_state);
+  if status.current_state == Stopped {
+    // Start the service if it is stopped
+    start_service(service)?;
   }
+  Ok(true)
 }
 
 #[cfg(target_os = "linux")]
@@ -524,120 +604,126 @@ fn to_linux_service_name(service: &str) -> Option<String> {
   ))
 }
 
+#[cfg(target_os = "linux")]
+fn require_linux_service_name(service: &str) -> Result<String, CultivationError> {
+  to_linux_service_name(service).ok_or_else(|| {
+    CultivationError::PlatformUnsupported(format!("{} has no known Linux service", service))
+  })
+}
+
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub fn service_status(service: String) -> bool {
+pub fn service_status(service: String) -> Result<bool, CultivationError> {
   // Change Windows service name into Linux service name
-  let service_lnx = to_linux_service_name(&service);
-  if service_lnx.is_none() {
-    return false;
-  }
-  let service_lnx = service_lnx.unwrap();
-  let status = Command::new("systemctl")
+  let Some(service_lnx) = to_linux_service_name(&service) else {
+    info!("{} has no known Linux service", service);
+    return Ok(false);
+  };
+  let Ok(status) = Command::new("systemctl")
     .arg("is-active")
     .arg(service_lnx)
     .stdout(Stdio::null())
-    .status();
-  if status.is_err() {
-    return false;
-  }
-  let status = status.unwrap().success();
-  if status {
-    status
-  } else {
-    start_service(service)
+    .status()
+  else {
+    info!(
+      "Could not ask systemctl about {}, is it installed?",
+      service
+    );
+    return Ok(false);
+  };
+  if !status.success() {
+    start_service(service)?;
   }
+  Ok(true)
 }
 
 #[cfg(windows)]
 #[tauri::command]
-pub fn start_service(service: String) -> bool {
-  println!("Starting service: {}", service);
-  let manager = match ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT) {
-    Ok(manager) => manager,
-    Err(_e) => return false,
-  };
-  let my_service = match manager.open_service(service, ServiceAccess::START) {
-    Ok(my_service) => my_service,
-    Err(_e) => return false,
-  };
-  match my_service.start(&[OsStr::new("Started service!")]) {
-    Ok(_s) => true,
-    Err(_e) => return false,
-  };
-  true
+pub fn start_service(service: String) -> Result<(), CultivationError> {
+  info!("Starting service: {}", service);
+  let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+    .map_err(service_err)?;
+  let my_service = manager
+    .open_service(service, ServiceAccess::START)
+    .map_err(service_err)?;
+  my_service
+    .start(&[OsStr::new("Started service!")])
+    .map_err(service_err)
 }
 
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub fn start_service(service: String) -> bool {
-  println!("Starting service: {}", service);
-  let service_lnx = to_linux_service_name(&service);
-  if service_lnx.is_none() {
-    return false;
-  }
-  let service_lnx = service_lnx.unwrap();
+pub fn start_service(service: String) -> Result<(), CultivationError> {
+  info!("Starting service: {}", service);
+  let service_lnx = require_linux_service_name(&service)?;
   Command::new("systemctl")
     .arg("start")
     .arg(service_lnx)
-    .spawn_its_fine_really(&format!("Failed to stop service {}", service))
-    .is_ok()
+    .spawn_its_fine_really(&format!("Failed to start service {}", service))
 }
 
 #[cfg(windows)]
 #[tauri::command]
-pub fn stop_service(service: String) -> bool {
-  println!("Stopping service: {}", service);
-  let manager = match ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT) {
-    Ok(manager) => manager,
-    Err(_e) => return false,
-  };
-  let my_service = match manager.open_service(service, ServiceAccess::STOP) {
-    Ok(my_service) => my_service,
-    Err(_e) => return false,
-  };
-  match my_service.stop() {
-    Ok(_s) => true,
-    Err(_e) => return false,
-  };
-  true
+pub fn stop_service(service: String) -> Result<(), CultivationError> {
+  info!("Stopping service: {}", service);
+  let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+    .map_err(service_err)?;
+  let my_service = manager
+    .open_service(service, ServiceAccess::STOP)
+    .map_err(service_err)?;
+  my_service.stop().map_err(service_err)?;
+  Ok(())
 }
 
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub fn stop_service(service: String) -> bool {
-  println!("Stopping service: {}", service);
-  let service_lnx = to_linux_service_name(&service);
-  if service_lnx.is_none() {
-    return false;
-  }
-  let service_lnx = service_lnx.unwrap();
+pub fn stop_service(service: String) -> Result<(), CultivationError> {
+  info!("Stopping service: {}", service);
+  let service_lnx = require_linux_service_name(&service)?;
   Command::new("systemctl")
     .arg("stop")
     .arg(service_lnx)
-    .spawn_its_fine_really(&format!("Failed to start service {}", service))
-    .is_ok()
+    .spawn_its_fine_really(&format!("Failed to stop service {}", service))
 }
 
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub fn wipe_registry(exec_name: String) {
-  println!("Wiping registry");
-  let regpath = format!("HKCU\\Software\\miHoYo\\{}", exec_name);
-  let mut cmd = aagl_wine_run("reg", None);
-  cmd.args([
-    "DELETE",
-    &regpath,
-    "/f",
-    "/v",
-    "MIHOYOSDK_ADL_PROD_OVERSEA_h1158948810",
-  ]);
-  let _ = cmd.spawn_its_fine_really("Error wiping registry");
+pub fn wipe_registry() -> Result<(), CultivationError> {
+  info!("Wiping registry");
+  let game = games::current();
+  let exe = game.executable(&game_version::game_exe()?);
+  let Some(login_value) = exe.login_value else {
+    warn!(
+      "Not wiping the login, where {} keeps it is not known",
+      game.name
+    );
+    return Ok(());
+  };
+  let regpath = format!("HKCU\\{}", exe.registry_key);
+
+  // reg DELETE fails on a value that isn't there, eg. when the game never ran
+  let exists = aagl_wine_run("reg", &[])?
+    .args(["QUERY", &regpath, "/v", login_value])
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .map_or(false, |status| status.success());
+  if !exists {
+    info!("No login to wipe under {}", regpath);
+    return Ok(());
+  }
+
+  let mut cmd = aagl_wine_run("reg", &[])?;
+  cmd.args(["DELETE", &regpath, "/f", "/v", login_value]);
+  cmd.spawn_its_fine_really("Error wiping registry")
 }
 
 #[cfg(target_os = "macos")]
 #[tauri::command]
-pub fn wipe_registry(_exec_name: String) {}
+pub fn wipe_registry() -> Result<(), CultivationError> {
+  // Nothing caches the login here
+  Ok(())
+}
 
 #[cfg(windows)]
 #[tauri::command]
@@ -658,52 +744,107 @@ pub fn get_platform() -> &'static str {
 
 #[cfg(not(target_os = "linux"))]
 #[tauri::command]
-pub async fn jvm_add_cap(_java_path: String) -> bool {
-  panic!("Not implemented");
+pub async fn jvm_add_cap(_java_path: String) -> Result<(), CultivationError> {
+  Err(CultivationError::unsupported("Setting JVM capabilities"))
 }
 
 #[cfg(not(target_os = "linux"))]
 #[tauri::command]
-pub async fn jvm_remove_cap(_java_path: String) -> bool {
-  panic!("Not implemented");
+pub async fn jvm_remove_cap(_java_path: String) -> Result<(), CultivationError> {
+  Err(CultivationError::unsupported("Setting JVM capabilities"))
 }
 
 #[cfg(target_os = "linux")]
-#[tauri::command]
-pub async fn jvm_add_cap(java_path: String) -> bool {
+fn resolve_java_bin(java_path: &str) -> Result<PathBuf, CultivationError> {
   let mut java_bin = if java_path.is_empty() {
-    which::which("java").expect("Java is not installed")
+    which::which("java").map_err(|_| CultivationError::Io("Java is not installed".to_string()))?
   } else {
-    PathBuf::from(&java_path)
+    PathBuf::from(java_path)
   };
   while java_bin.is_symlink() {
-    java_bin = java_bin.read_link().unwrap()
+    java_bin = java_bin.read_link()?
   }
-  println!("Removing cap on {:?}", &java_bin);
+  Ok(java_bin)
+}
+
+#[cfg(target_os = "linux")]
+#[tauri::command]
+pub async fn jvm_add_cap(java_path: String) -> Result<(), CultivationError> {
+  let java_bin = resolve_java_bin(&java_path)?;
+  info!("Setting cap on {:?}", &java_bin);
   Command::new("setcap")
     .arg("CAP_NET_BIND_SERVICE=+eip")
     .arg(java_bin)
     .as_root_gui()
     .spawn_its_fine_really(&format!("Failed to add cap to {}", java_path))
-    .is_ok()
 }
 
 #[cfg(target_os = "linux")]
 #[tauri::command]
-pub async fn jvm_remove_cap(java_path: String) -> bool {
-  let mut java_bin = if java_path.is_empty() {
-    which::which("java").expect("Java is not installed")
-  } else {
-    PathBuf::from(&java_path)
-  };
-  while java_bin.is_symlink() {
-    java_bin = java_bin.read_link().unwrap()
-  }
-  println!("Setting cap on {:?}", &java_bin);
+pub async fn jvm_remove_cap(java_path: String) -> Result<(), CultivationError> {
+  let java_bin = resolve_java_bin(&java_path)?;
+  info!("Removing cap on {:?}", &java_bin);
   Command::new("setcap")
     .arg("-r")
     .arg(java_bin)
     .as_root_gui()
     .spawn_its_fine_really(&format!("Failed to remove cap from {}", java_path))
-    .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &str) -> Vec<String> {
+    parse_launch_args(args).unwrap()
+  }
+
+  #[test]
+  fn keeps_quoted_arguments_together() {
+    assert_eq!(
+      parse(r#"-screen-fullscreen 0  -monitor "2" -name 'Two words'"#),
+      [
+        "-screen-fullscreen",
+        "0",
+        "-monitor",
+        "2",
+        "-name",
+        "Two words"
+      ]
+    );
+    assert_eq!(parse(r#"-path="C:\My Games""#), [r"-path=C:\My Games"]);
+    assert!(parse(" \t ").is_empty());
+  }
+
+  #[test]
+  fn keeps_backslashes() {
+    assert_eq!(
+      parse(r"-logFile C:\Users\me\game.log"),
+      ["-logFile", r"C:\Users\me\game.log"]
+    );
+    assert_eq!(
+      parse(r#""C:\Games\" \\server\share"#),
+      [r"C:\Games\", r"\\server\share"]
+    );
+  }
+
+  #[test]
+  fn keeps_empty_quotes_as_empty_arguments() {
+    assert_eq!(parse(r#"-a "" -b ''"#), ["-a", "", "-b", ""]);
+  }
+
+  #[test]
+  fn keeps_the_other_quote_inside_quotes() {
+    assert_eq!(
+      parse(r#"-name "it's" -title '"quoted"'"#),
+      ["-name", "it's", "-title", r#""quoted""#]
+    );
+  }
+
+  #[test]
+  fn refuses_unbalanced_quotes() {
+    assert!(parse_launch_args(r#"-monitor "2"#).is_err());
+    assert!(parse_launch_args("-name it's").is_err());
+    assert!(parse_launch_args(r#"-title '"quoted"'""#).is_err());
+  }
 }
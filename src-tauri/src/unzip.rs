@@ -1,6 +1,9 @@
+use crate::error::CultivationError;
+use crate::snapshots;
 use std::fs::{read_dir, File};
 use std::path;
 use std::thread;
+use tracing::{debug, error, info, warn};
 use unrar::archive::Archive;
 
 #[tauri::command]
@@ -10,22 +13,16 @@ pub fn unzip(
   destpath: String,
   top_level: Option<bool>,
   folder_if_loose: Option<bool>,
-) {
+) -> Result<(), CultivationError> {
   // Read file TODO: replace test file
-  let f = match File::open(&zipfile) {
-    Ok(f) => f,
-    Err(e) => {
-      println!("Failed to open zip file: {}", e);
-      return;
-    }
-  };
+  let f = File::open(&zipfile)?;
 
   let write_path = path::PathBuf::from(&destpath);
 
   // Get a list of all current directories
   let mut dirs = vec![];
-  for entry in read_dir(&write_path).unwrap() {
-    let entry = entry.unwrap();
+  for entry in read_dir(&write_path)? {
+    let entry = entry?;
     let entry_path = entry.path();
     if entry_path.is_dir() {
       dirs.push(entry_path);
@@ -40,58 +37,52 @@ pub fn unzip(
 
     if folder_if_loose.unwrap_or(false) {
       // Create a new folder with the same name as the zip file
-      let mut file_name = path::Path::new(&zipfile)
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap();
-
-      // remove ".zip" from the end of the file name
-      file_name = &file_name[..file_name.len() - 4];
+      let file_name = path::Path::new(&zipfile)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
 
       let new_path = full_path.join(file_name);
-      match std::fs::create_dir_all(&new_path) {
-        Ok(_) => {}
-        Err(e) => {
-          println!("Failed to create directory: {}", e);
-          return;
-        }
+      if let Err(e) = std::fs::create_dir_all(&new_path) {
+        emit_extract_err(&window, &zipfile, e.into());
+        return;
       };
 
       full_path = new_path;
     }
 
-    println!("Is rar file? {}", zipfile.ends_with(".rar"));
-
-    let name;
-    let success;
+    debug!("Is rar file? {}", zipfile.ends_with(".rar"));
 
-    // If file ends in zip, OR is unknown, extract as zip, otherwise extract as rar
-    if zipfile.ends_with(".rar") {
-      success = extract_rar(&zipfile, &f, &full_path, top_level.unwrap_or(true));
+    // Keep what the archive is about to overwrite in the game or mod folders
+    if snapshots::covers(&full_path) {
+      let archive_name = path::Path::new(&zipfile)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+      let snapshotted = archive_targets(&zipfile, &f, &full_path, top_level.unwrap_or(true))
+        .and_then(|targets| snapshots::take(format!("Extract {}", archive_name), targets));
+      if let Err(e) = snapshotted {
+        emit_extract_err(&window, &zipfile, e);
+        return;
+      }
+    }
 
-      let archive = Archive::new(zipfile.clone());
-      name = archive.list().unwrap().next().unwrap().unwrap().filename;
+    let extracted = if zipfile.ends_with(".rar") {
+      extract_rar(&zipfile, &f, &full_path, top_level.unwrap_or(true))
     } else if zipfile.ends_with(".7z") {
-      success = extract_7z(&zipfile, &f, &full_path, top_level.unwrap_or(true));
-
-      name = String::from("banana");
+      extract_7z(&zipfile, &f, &full_path, top_level.unwrap_or(true))
     } else {
-      success = extract_zip(&zipfile, &f, &full_path, top_level.unwrap_or(true));
-
-      // Get the name of the inenr file in the zip file
-      let mut zip = zip::ZipArchive::new(&f).unwrap();
-      let file = zip.by_index(0).unwrap();
-      name = file.name().to_string();
-    }
-
-    if !success {
-      let mut res_hash = std::collections::HashMap::new();
-
-      res_hash.insert("path".to_string(), zipfile.to_string());
+      // If file ends in zip, OR is unknown, extract as zip
+      extract_zip(&zipfile, &f, &full_path, top_level.unwrap_or(true))
+    };
 
-      window.emit("download_error", &res_hash).unwrap();
-    }
+    let name = match extracted {
+      Ok(name) => name,
+      Err(e) => {
+        emit_extract_err(&window, &zipfile, e);
+        return;
+      }
+    };
 
     // If the contents is a jar file, emit that we have extracted a new jar file
     if name.ends_with(".jar") {
@@ -127,8 +118,7 @@ pub fn unzip(
       Ok(_) => {
         // Get any new directory that could have been created
         let mut new_dir: String = String::new();
-        for entry in read_dir(&write_path).unwrap() {
-          let entry = entry.unwrap();
+        for entry in read_dir(&write_path).into_iter().flatten().flatten() {
           let entry_path = entry.path();
           if entry_path.is_dir() && !dirs.contains(&entry_path) {
             new_dir = entry_path.to_str().unwrap().to_string();
@@ -140,68 +130,154 @@ pub fn unzip(
         res_hash.insert("new_folder", new_dir);
 
         window.emit("extract_end", &res_hash).unwrap();
-        println!("Deleted zip file: {}", zipfile);
+        info!("Deleted zip file: {}", zipfile);
       }
       Err(e) => {
-        println!("Failed to delete zip file: {}", e);
+        warn!("Failed to delete zip file: {}", e);
       }
     };
   });
+
+  Ok(())
 }
 
-fn extract_rar(rarfile: &str, _f: &File, full_path: &path::Path, _top_level: bool) -> bool {
-  let archive = Archive::new(rarfile.to_string());
+fn emit_extract_err(window: &tauri::Window, zipfile: &str, e: CultivationError) {
+  error!("Failed to extract {}: {}", zipfile, e);
 
-  let mut open_archive = archive
-    .extract_to(full_path.to_str().unwrap().to_string())
-    .unwrap();
+  let mut res_hash = std::collections::HashMap::new();
 
-  match open_archive.process() {
-    Ok(_) => {
-      println!(
-        "Extracted rar file to: {}",
-        full_path.to_str().unwrap_or("Error")
-      );
+  res_hash.insert("path".to_string(), zipfile.to_string());
+  res_hash.insert("error".to_string(), e.message().to_string());
+
+  window.emit("download_error", &res_hash).unwrap();
+}
 
-      true
+// Where each file in the archive will be extracted to, the same way the extractors below do it
+fn archive_targets(
+  archive: &str,
+  f: &File,
+  full_path: &path::Path,
+  top_level: bool,
+) -> Result<Vec<path::PathBuf>, CultivationError> {
+  let names: Vec<path::PathBuf> = if archive.ends_with(".rar") {
+    let rar_err = |e: String| CultivationError::Archive(format!("Failed to read rar file: {}", e));
+    let mut names = vec![];
+    for entry in Archive::new(archive.to_string())
+      .list()
+      .map_err(|e| rar_err(e.to_string()))?
+    {
+      names.push(path::PathBuf::from(
+        entry.map_err(|e| rar_err(e.to_string()))?.filename,
+      ));
     }
-    Err(e) => {
-      println!("Failed to extract rar file: {}", e);
-      false
+    names
+  } else if archive.ends_with(".7z") {
+    // The names are in the header, nothing has to be decompressed
+    let mut file = File::open(archive)?;
+    let len = file.metadata()?.len();
+    sevenz_rust::Archive::read(&mut file, len, &[])?
+      .files
+      .iter()
+      .filter(|entry| !entry.is_directory())
+      .map(|entry| path::PathBuf::from(entry.name()))
+      .collect()
+  } else {
+    let mut zip = zip::ZipArchive::new(f)?;
+    let mut names = vec![];
+    for i in 0..zip.len() {
+      names.push(zip.by_index(i)?.mangled_name());
     }
-  }
+
+    // zip_extract only strips the top level folder if every entry is in it
+    let toplevel: Option<path::PathBuf> = names
+      .first()
+      .map(|name| name.components().take(1).collect());
+    let strip = top_level
+      && names.len() >= 2
+      && names
+        .iter()
+        .all(|name| toplevel.as_ref().map_or(false, |dir| name.starts_with(dir)));
+    if strip {
+      let toplevel = toplevel.unwrap();
+      names = names
+        .into_iter()
+        .filter_map(|name| name.strip_prefix(&toplevel).ok().map(|p| p.to_path_buf()))
+        .collect();
+    }
+    names
+  };
+
+  Ok(
+    names
+      .into_iter()
+      .filter(|name| !name.as_os_str().is_empty())
+      .map(|name| full_path.join(name))
+      .collect(),
+  )
 }
 
-fn extract_zip(_zipfile: &str, f: &File, full_path: &path::Path, top_level: bool) -> bool {
-  match zip_extract::extract(f, full_path, top_level) {
-    Ok(_) => {
-      println!(
-        "Extracted zip file to: {}",
-        full_path.to_str().unwrap_or("Error")
-      );
+// Each extractor returns the name of the first entry in the archive
+fn extract_rar(
+  rarfile: &str,
+  _f: &File,
+  full_path: &path::Path,
+  _top_level: bool,
+) -> Result<String, CultivationError> {
+  let rar_err = |e: String| CultivationError::Archive(format!("Failed to extract rar file: {}", e));
+  let archive = Archive::new(rarfile.to_string());
 
-      true
-    }
-    Err(e) => {
-      println!("Failed to extract zip file: {}", e);
-      false
-    }
-  }
+  let mut open_archive = archive
+    .extract_to(full_path.to_str().unwrap_or_default().to_string())
+    .map_err(|e| rar_err(e.to_string()))?;
+
+  open_archive.process().map_err(|e| rar_err(e.to_string()))?;
+  info!(
+    "Extracted rar file to: {}",
+    full_path.to_str().unwrap_or("Error")
+  );
+
+  let name = Archive::new(rarfile.to_string())
+    .list()
+    .map_err(|e| rar_err(e.to_string()))?
+    .next()
+    .ok_or_else(|| rar_err("archive is empty".to_string()))?
+    .map_err(|e| rar_err(e.to_string()))?
+    .filename;
+
+  Ok(name)
 }
 
-fn extract_7z(sevenzfile: &str, _f: &File, full_path: &path::Path, _top_level: bool) -> bool {
-  match sevenz_rust::decompress_file(sevenzfile, full_path) {
-    Ok(_) => {
-      println!(
-        "Extracted 7zip file to: {}",
-        full_path.to_str().unwrap_or("Error")
-      );
+fn extract_zip(
+  _zipfile: &str,
+  f: &File,
+  full_path: &path::Path,
+  top_level: bool,
+) -> Result<String, CultivationError> {
+  zip_extract::extract(f, full_path, top_level)
+    .map_err(|e| CultivationError::Archive(format!("Failed to extract zip file: {}", e)))?;
+  info!(
+    "Extracted zip file to: {}",
+    full_path.to_str().unwrap_or("Error")
+  );
+
+  // Get the name of the inner file in the zip file
+  let mut zip = zip::ZipArchive::new(f)?;
+  let file = zip.by_index(0)?;
+
+  Ok(file.name().to_string())
+}
 
-      true
-    }
-    Err(e) => {
-      println!("Failed to extract 7zip file: {}", e);
-      false
-    }
-  }
+fn extract_7z(
+  sevenzfile: &str,
+  _f: &File,
+  full_path: &path::Path,
+  _top_level: bool,
+) -> Result<String, CultivationError> {
+  sevenz_rust::decompress_file(sevenzfile, full_path)?;
+  info!(
+    "Extracted 7zip file to: {}",
+    full_path.to_str().unwrap_or("Error")
+  );
+
+  Ok(String::from("banana"))
 }
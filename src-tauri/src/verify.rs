@@ -0,0 +1,493 @@
+use crate::error::CultivationError;
+use crate::file_helpers;
+use crate::game_version;
+use crate::paths;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+// The game lists its files in pkg_version, and voice packs in eg. Audio_English(US)_pkg_version
+const PKG_VERSION_SUFFIX: &str = "pkg_version";
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKind {
+  Md5,
+  Sha256,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileEntry {
+  pub size: Option<u64>,
+  pub hash: String,
+}
+
+/**
+ * What a game install should look like, keyed by path relative to the game directory with /
+ * separators. Read from pkg_version, or saved by save_game_manifest.
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GameManifest {
+  pub hash: HashKind,
+  pub created: Option<u64>,
+  pub files: BTreeMap<String, FileEntry>,
+}
+
+// A line of pkg_version
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PkgVersionEntry {
+  remote_name: String,
+  md5: String,
+  file_size: Option<u64>,
+}
+
+#[derive(Serialize, Clone)]
+struct VerifyProgress {
+  checked: usize,
+  total: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct VerifyReport {
+  // "pkg_version" or the name of the saved manifest
+  pub source: String,
+  pub checked: usize,
+  pub missing: Vec<String>,
+  pub modified: Vec<String>,
+  // Only for saved manifests, pkg_version doesn't list everything the game writes
+  pub extra: Vec<String>,
+}
+
+/**
+ * Hashes the game's files and compares them with `manifest` (see save_game_manifest),
+ * or with the game's own pkg_version if that's not given.
+ * Sends verify_progress events while hashing.
+ */
+#[tauri::command]
+pub async fn verify_game_files(
+  window: tauri::Window,
+  manifest: Option<String>,
+) -> Result<VerifyReport, CultivationError> {
+  let game_dir = game_dir()?;
+
+  tauri::async_runtime::spawn_blocking(move || {
+    let (source, expected) = match manifest {
+      Some(name) => (name.clone(), load_manifest(&name)?),
+      None => (PKG_VERSION_SUFFIX.to_string(), read_pkg_version(&game_dir)?),
+    };
+
+    verify(&game_dir, &expected, source, |progress| {
+      if let Err(e) = window.emit("verify_progress", progress) {
+        warn!("Failed to send verify progress: {}", e);
+      }
+    })
+  })
+  .await
+  .map_err(|e| CultivationError::Io(format!("Verifying was interrupted: {}", e)))?
+}
+
+/**
+ * Saves the hashes of every file in the game directory as `name`, to verify against later,
+ * eg. before trying out mods. Returns the number of files.
+ */
+#[tauri::command]
+pub async fn save_game_manifest(name: String) -> Result<usize, CultivationError> {
+  let game_dir = game_dir()?;
+  let path = manifest_path(&name)?;
+
+  tauri::async_runtime::spawn_blocking(move || {
+    let files = list_files(&game_dir)?;
+    let entries = files
+      .par_iter()
+      .map(|relative| {
+        let full = game_dir.join(relative);
+        let entry = FileEntry {
+          size: Some(fs::metadata(&full)?.len()),
+          hash: file_helpers::sha256_file(&full)?,
+        };
+        Ok((relative.clone(), entry))
+      })
+      .collect::<Result<BTreeMap<_, _>, CultivationError>>()?;
+
+    let manifest = GameManifest {
+      hash: HashKind::Sha256,
+      created: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs()),
+      files: entries,
+    };
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, serde_json::to_string_pretty(&manifest)?)?;
+
+    info!("Saved {} game files as {:?}", manifest.files.len(), path);
+    Ok(manifest.files.len())
+  })
+  .await
+  .map_err(|e| CultivationError::Io(format!("Saving the manifest was interrupted: {}", e)))?
+}
+
+#[tauri::command]
+pub fn list_game_manifests() -> Result<Vec<String>, CultivationError> {
+  let dir = manifests_dir();
+  if !dir.exists() {
+    return Ok(vec![]);
+  }
+
+  let mut names = vec![];
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.extension().map_or(false, |ext| ext == "json") {
+      names.push(path.file_stem().unwrap().to_string_lossy().to_string());
+    }
+  }
+
+  names.sort();
+  Ok(names)
+}
+
+fn verify(
+  game_dir: &Path,
+  expected: &GameManifest,
+  source: String,
+  on_progress: impl Fn(VerifyProgress) + Sync,
+) -> Result<VerifyReport, CultivationError> {
+  let total = expected.files.len();
+  let checked = AtomicUsize::new(0);
+  let last_progress = Mutex::new(Instant::now());
+
+  // Some(true) is modified, None is missing
+  let results = expected
+    .files
+    .par_iter()
+    .map(|(relative, entry)| {
+      let path = game_dir.join(relative);
+      let result = if !path.is_file() {
+        None
+      } else {
+        Some(!matches(&path, entry, expected.hash)?)
+      };
+
+      let done = checked.fetch_add(1, Ordering::Relaxed) + 1;
+      let mut last = last_progress.lock().unwrap();
+      if done == total || last.elapsed() >= PROGRESS_INTERVAL {
+        *last = Instant::now();
+        on_progress(VerifyProgress {
+          checked: done,
+          total,
+        });
+      }
+
+      Ok((relative, result))
+    })
+    .collect::<Result<Vec<_>, CultivationError>>()?;
+
+  let mut report = VerifyReport {
+    source,
+    checked: total,
+    ..Default::default()
+  };
+  for (relative, result) in results {
+    match result {
+      None => report.missing.push(relative.clone()),
+      Some(true) => report.modified.push(relative.clone()),
+      Some(false) => {}
+    }
+  }
+
+  if expected.hash == HashKind::Sha256 {
+    report.extra = list_files(game_dir)?
+      .into_iter()
+      .filter(|relative| !expected.files.contains_key(relative))
+      .collect();
+  }
+
+  info!(
+    "Verified {} files against {}: {} missing, {} modified, {} extra",
+    total,
+    report.source,
+    report.missing.len(),
+    report.modified.len(),
+    report.extra.len()
+  );
+  Ok(report)
+}
+
+fn matches(path: &Path, entry: &FileEntry, hash: HashKind) -> Result<bool, CultivationError> {
+  // Cheap to check first, and enough to tell most changes
+  if let Some(size) = entry.size {
+    if fs::metadata(path)?.len() != size {
+      return Ok(false);
+    }
+  }
+
+  let actual = match hash {
+    HashKind::Md5 => file_helpers::md5_file(path)?,
+    HashKind::Sha256 => file_helpers::sha256_file(path)?,
+  };
+  Ok(actual.eq_ignore_ascii_case(&entry.hash))
+}
+
+// Every *pkg_version in the game directory, merged
+fn read_pkg_version(game_dir: &Path) -> Result<GameManifest, CultivationError> {
+  let mut files = BTreeMap::new();
+
+  for entry in fs::read_dir(game_dir)? {
+    let path = entry?.path();
+    let is_pkg_version = path.file_name().map_or(false, |name| {
+      name.to_string_lossy().ends_with(PKG_VERSION_SUFFIX)
+    });
+    if !is_pkg_version || !path.is_file() {
+      continue;
+    }
+
+    for line in fs::read_to_string(&path)?.lines() {
+      if line.trim().is_empty() {
+        continue;
+      }
+
+      let entry: PkgVersionEntry = serde_json::from_str(line)
+        .map_err(|e| CultivationError::Config(format!("{:?} has an invalid line: {}", path, e)))?;
+      files.insert(
+        entry.remote_name,
+        FileEntry {
+          size: entry.file_size,
+          hash: entry.md5,
+        },
+      );
+    }
+  }
+
+  if files.is_empty() {
+    return Err(CultivationError::Config(format!(
+      "There is no pkg_version in {:?}, verify against a saved manifest instead",
+      game_dir
+    )));
+  }
+
+  Ok(GameManifest {
+    hash: HashKind::Md5,
+    created: None,
+    files,
+  })
+}
+
+// Paths relative to `dir`, with / separators
+fn list_files(dir: &Path) -> Result<Vec<String>, CultivationError> {
+  let mut files = vec![];
+
+  for entry in WalkDir::new(dir).follow_links(false) {
+    let entry = entry.map_err(|e| CultivationError::Io(e.to_string()))?;
+    if !entry.file_type().is_file() {
+      continue;
+    }
+
+    let relative = entry.path().strip_prefix(dir).unwrap();
+    let relative: Vec<String> = relative
+      .components()
+      .map(|part| part.as_os_str().to_string_lossy().to_string())
+      .collect();
+    files.push(relative.join("/"));
+  }
+
+  Ok(files)
+}
+
+fn game_dir() -> Result<PathBuf, CultivationError> {
+  let game_exe = game_version::game_exe()?;
+  Ok(game_exe.parent().unwrap_or(Path::new(".")).to_path_buf())
+}
+
+fn manifests_dir() -> PathBuf {
+  paths::data_dir().join("game_manifests")
+}
+
+fn manifest_path(name: &str) -> Result<PathBuf, CultivationError> {
+  let valid = !name.is_empty()
+    && name
+      .chars()
+      .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.' || c == ' ')
+    && !name.starts_with('.');
+  if !valid {
+    return Err(CultivationError::Config(format!(
+      "'{}' can't be used as a manifest name, use letters, numbers, spaces, - and _",
+      name
+    )));
+  }
+
+  Ok(manifests_dir().join(format!("{}.json", name)))
+}
+
+fn load_manifest(name: &str) -> Result<GameManifest, CultivationError> {
+  let path = manifest_path(name)?;
+  if !path.is_file() {
+    return Err(CultivationError::Config(format!(
+      "There is no saved manifest named '{}'",
+      name
+    )));
+  }
+
+  serde_json::from_str(&fs::read_to_string(&path)?)
+    .map_err(|e| CultivationError::Config(format!("{:?} could not be read: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // md5 of "hello"
+  const HELLO_MD5: &str = "5d41402abc4b2a76b9719d911017c592";
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+      "cultivation-verify-{}-{}",
+      name,
+      std::process::id()
+    ));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn pkg_version_line(name: &str, md5: &str, size: u64) -> String {
+    format!(
+      "{{\"remoteName\": \"{}\", \"md5\": \"{}\", \"fileSize\": {}}}\n",
+      name, md5, size
+    )
+  }
+
+  #[test]
+  fn merges_every_pkg_version() {
+    let dir = temp_dir("merge");
+    fs::write(
+      dir.join("pkg_version"),
+      pkg_version_line("GenshinImpact.exe", HELLO_MD5, 5) + "\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.join("Audio_English(US)_pkg_version"),
+      pkg_version_line(
+        "GenshinImpact_Data/StreamingAssets/Audio/1.pck",
+        HELLO_MD5,
+        5,
+      ),
+    )
+    .unwrap();
+    // Not a pkg_version, so not read
+    fs::write(dir.join("pkg_version.bak"), "not json").unwrap();
+
+    let manifest = read_pkg_version(&dir).unwrap();
+    assert_eq!(manifest.hash, HashKind::Md5);
+    assert_eq!(
+      manifest.files.keys().collect::<Vec<_>>(),
+      vec![
+        "GenshinImpact.exe",
+        "GenshinImpact_Data/StreamingAssets/Audio/1.pck"
+      ]
+    );
+    assert_eq!(manifest.files["GenshinImpact.exe"].size, Some(5));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn refuses_a_missing_or_broken_pkg_version() {
+    let dir = temp_dir("broken");
+    assert!(matches!(
+      read_pkg_version(&dir),
+      Err(CultivationError::Config(_))
+    ));
+
+    fs::write(dir.join("pkg_version"), "{\"remoteName\": \"a\"}\n").unwrap();
+    assert!(matches!(
+      read_pkg_version(&dir),
+      Err(CultivationError::Config(_))
+    ));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn reports_missing_and_modified_files() {
+    let dir = temp_dir("md5");
+    fs::write(dir.join("same"), "hello").unwrap();
+    // Same size, so only the hash tells
+    fs::write(dir.join("changed"), "jello").unwrap();
+    fs::write(dir.join("resized"), "hello!").unwrap();
+    fs::write(dir.join("unlisted"), "hello").unwrap();
+
+    let files = ["same", "changed", "resized", "gone"]
+      .iter()
+      .map(|name| {
+        (
+          name.to_string(),
+          FileEntry {
+            size: Some(5),
+            hash: HELLO_MD5.to_uppercase(),
+          },
+        )
+      })
+      .collect();
+    let manifest = GameManifest {
+      hash: HashKind::Md5,
+      created: None,
+      files,
+    };
+
+    let report = verify(&dir, &manifest, "pkg_version".to_string(), |_| {}).unwrap();
+    assert_eq!(report.checked, 4);
+    assert_eq!(report.missing, vec!["gone"]);
+    assert_eq!(report.modified, vec!["changed", "resized"]);
+    // pkg_version doesn't list everything, so nothing is extra
+    assert!(report.extra.is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn reports_files_a_saved_manifest_does_not_have() {
+    let dir = temp_dir("sha256");
+    fs::create_dir_all(dir.join("mods")).unwrap();
+    fs::write(dir.join("game.exe"), "hello").unwrap();
+    fs::write(dir.join("mods").join("mod.dll"), "hello").unwrap();
+
+    let mut files = BTreeMap::new();
+    files.insert(
+      "game.exe".to_string(),
+      FileEntry {
+        size: None,
+        hash: file_helpers::sha256_file(&dir.join("game.exe")).unwrap(),
+      },
+    );
+    let manifest = GameManifest {
+      hash: HashKind::Sha256,
+      created: None,
+      files,
+    };
+
+    let report = verify(&dir, &manifest, "before mods".to_string(), |_| {}).unwrap();
+    assert!(report.missing.is_empty() && report.modified.is_empty());
+    assert_eq!(report.extra, vec!["mods/mod.dll"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn only_takes_plain_manifest_names() {
+    assert!(manifest_path("before mods").is_ok());
+    assert!(manifest_path("../configuration").is_err());
+    assert!(manifest_path(".hidden").is_err());
+    assert!(manifest_path("").is_err());
+  }
+}
@@ -1,3 +1,4 @@
+use crate::error::CultivationError;
 use http::header;
 use once_cell::sync::Lazy;
 use reqwest::header::{CONTENT_TYPE, USER_AGENT};
@@ -13,15 +14,8 @@ static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
   client.build().unwrap()
 });
 
-pub(crate) async fn query(site: &str) -> String {
-  CLIENT
-    .get(site)
-    .send()
-    .await
-    .expect("Failed to get web response")
-    .text()
-    .await
-    .unwrap()
+pub(crate) async fn query(site: &str) -> Result<String, CultivationError> {
+  Ok(CLIENT.get(site).send().await?.text().await?)
 }
 
 #[tauri::command]
@@ -44,7 +38,7 @@ pub(crate) async fn valid_url(url: String) -> bool {
 }
 
 #[tauri::command]
-pub async fn web_get(url: String) -> String {
+pub async fn web_get(url: String) -> Result<String, CultivationError> {
   // Send a GET request to the specified URL and send the response body back to the client.
   query(&url).await
 }
@@ -15,11 +15,10 @@ import { ExtrasMenu } from './components/menu/ExtrasMenu'
 import Notification from './components/common/Notification'
 import GamePathNotify from './components/menu/GamePathNotify'
 
-import { getConfig, getConfigOption, setConfigOption } from '../utils/configuration'
+import { getConfig, getConfigOption, getDataDir, setConfigOption } from '../utils/configuration'
 import { invoke } from '@tauri-apps/api'
 import { getVersion } from '@tauri-apps/api/app'
 import { listen } from '@tauri-apps/api/event'
-import { dataDir } from '@tauri-apps/api/path'
 import { appWindow } from '@tauri-apps/api/window'
 import { unpatchGame } from '../utils/rsa'
 import DownloadHandler from '../utils/download'
@@ -87,7 +86,7 @@ export class Main extends React.Component<IProps, IState> {
       if (wasPatched) {
         const unpatched = await unpatchGame()
 
-        if (unpatched) {
+        if (!unpatched) {
           alert(`Could not unpatch game! (Delete version.dll in your game folder)`)
         }
       }
@@ -169,7 +168,7 @@ export class Main extends React.Component<IProps, IState> {
     if (!cert_generated) {
       // Generate the certificate
       await invoke('generate_ca_files', {
-        path: (await dataDir()) + 'cultivation',
+        path: await getDataDir(),
       })
 
       await setConfigOption('cert_generated', true)
@@ -3,7 +3,7 @@ import Checkbox from './common/Checkbox'
 import BigButton from './common/BigButton'
 import TextInput from './common/TextInput'
 import HelpButton from './common/HelpButton'
-import { getConfig, saveConfig, setConfigOption } from '../../utils/configuration'
+import { Configuration, getConfig, getDataDir, saveConfig, setConfigOption } from '../../utils/configuration'
 import { translate } from '../../utils/language'
 import { invoke } from '@tauri-apps/api/tauri'
 
@@ -11,7 +11,6 @@ import Server from '../../resources/icons/server.svg'
 import Plus from '../../resources/icons/plus.svg'
 
 import './ServerLaunchSection.css'
-import { dataDir } from '@tauri-apps/api/path'
 import { GrasscutterElevation } from './menu/Options'
 import { getGameExecutable, getGameVersion, getGrasscutterJar } from '../../utils/game'
 import { patchGame, unpatchGame } from '../../utils/rsa'
@@ -76,6 +75,17 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
     listen('start_grasscutter', async () => {
       this.launchServer()
     })
+
+    // configuration.json was edited outside of Cultivation
+    listen('config_changed', ({ payload }: { payload: Configuration }) => {
+      this.setState({
+        grasscutterEnabled: payload.toggle_grasscutter || false,
+        ip: payload.last_ip || '',
+        port: payload.last_port?.toString() || '',
+        httpsEnabled: payload.https_enabled || false,
+        unElevated: payload.un_elevated || false,
+      })
+    })
   }
 
   async componentDidMount() {
@@ -86,7 +96,7 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
       buttonLabel: await translate('main.launch_button'),
       checkboxLabel: await translate('main.gc_enable'),
       ip: config.last_ip || '',
-      port: config.last_port || '',
+      port: config.last_port?.toString() || '',
       ipPlaceholder: await translate('main.ip_placeholder'),
       portPlaceholder: await translate('help.port_placeholder'),
       httpsLabel: await translate('main.https_enable'),
@@ -137,6 +147,16 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
       }
     }
 
+    // After the last chance to cancel, hooks may start things that expect the game to follow.
+    // Hooks set to abort can still call the launch off.
+    const hooksPassed = await invoke('run_hooks', { stage: 'pre_launch' })
+      .then(() => true)
+      .catch((e) => {
+        alert('Not launching the game: ' + e?.message)
+        return false
+      })
+    if (!hooksPassed) return
+
     // Connect to proxy
     if (config.toggle_grasscutter) {
       const game_exe = await getGameExecutable()
@@ -159,11 +179,6 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
           return
         }
 
-        if (gameVersion?.major == 3 && gameVersion?.minor < 1) {
-          alert('Game version is too old for RSA patching. Please disable RSA patching in the settings and try again.')
-          return
-        }
-
         const patched = await patchGame()
 
         if (!patched) {
@@ -186,7 +201,7 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
           addr: (this.state.httpsEnabled ? 'https' : 'http') + '://' + this.state.ip + ':' + this.state.port,
         })
         // Connect to proxy
-        await invoke('connect', { port: 8365, certificatePath: (await dataDir()) + 'cultivation/ca' })
+        await invoke('connect', { port: 8365, certificatePath: (await getDataDir()) + '/ca' })
       }
 
       // Open server as well if the options are set
@@ -198,11 +213,8 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
     }
 
     if (config.wipe_login) {
-      // First wipe registry if we have to
-      await invoke('wipe_registry', {
-        // The exe is always PascalCase so we can get the dir using regex
-        execName: (await getGameExecutable())?.split('.exe')[0].replace(/([a-z\d])([A-Z])/g, '$1 $2'),
-      })
+      // First wipe registry if we have to, the game can still launch if this fails
+      await invoke('wipe_registry').catch(console.warn)
     }
 
     // Launch the program
@@ -228,7 +240,9 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
           })
         }
       }
-    else alert('Game not found! At: ' + (exe || config.game_install_path))
+    else return alert('Game not found! At: ' + (exe || config.game_install_path))
+
+    invoke('run_hooks', { stage: 'post_launch' }).catch(console.error)
   }
 
   async launchServer(proc_name?: string) {
@@ -247,7 +261,7 @@ export default class ServerLaunchSection extends React.Component<IProps, IState>
 
     if (config.auto_mongodb) {
       // Check if MongoDB is running and start it if not
-      invoke('service_status', { service: 'MongoDB' })
+      invoke('service_status', { service: 'MongoDB' }).catch(console.error)
     }
 
     let jarFolder = config.grasscutter_path
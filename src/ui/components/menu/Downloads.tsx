@@ -4,11 +4,10 @@ import Tr from '../../../utils/language'
 import DownloadHandler from '../../../utils/download'
 import { unzip } from '../../../utils/zipUtils'
 import BigButton from '../common/BigButton'
-import { dataDir } from '@tauri-apps/api/path'
 
 import './Downloads.css'
 import Divider from './Divider'
-import { getConfigOption } from '../../../utils/configuration'
+import { getConfigOption, getDataDir } from '../../../utils/configuration'
 import { invoke } from '@tauri-apps/api'
 import { listen } from '@tauri-apps/api/event'
 import HelpButton from '../common/HelpButton'
@@ -109,8 +108,7 @@ export default class Downloads extends React.Component<IProps, IState> {
 
     // Set to default if not set
     if (!path || path === '') {
-      const appdata = await dataDir()
-      folderPath = appdata + 'cultivation/grasscutter'
+      folderPath = (await getDataDir()) + '/grasscutter'
 
       // Early return since its formatted properly
       return folderPath
@@ -126,7 +124,7 @@ export default class Downloads extends React.Component<IProps, IState> {
   }
 
   async getCultivationFolder() {
-    const folderPath = (await dataDir()) + 'cultivation'
+    const folderPath = await getDataDir()
 
     return folderPath
   }
@@ -1,10 +1,9 @@
 import React from 'react'
 import { invoke } from '@tauri-apps/api'
-import { dataDir } from '@tauri-apps/api/path'
 import DirInput from '../common/DirInput'
 import Menu from './Menu'
 import Tr, { getLanguages } from '../../../utils/language'
-import { setConfigOption, getConfig, getConfigOption, Configuration } from '../../../utils/configuration'
+import { setConfigOption, getConfig, getConfigOption, getDataDir, Configuration } from '../../../utils/configuration'
 import Checkbox from '../common/Checkbox'
 import Divider from './Divider'
 import { getThemeList } from '../../../utils/themes'
@@ -267,14 +266,14 @@ export default class Options extends React.Component<IProps, IState> {
 
     if (!isUrl) {
       const filename = value.replace(/\\/g, '/').split('/').pop()
-      const localBgPath = (await dataDir()).replace(/\\/g, '/')
+      const localBgPath = (await getDataDir()).replace(/\\/g, '/')
 
-      await setConfigOption('custom_background', `${localBgPath}/cultivation/bg/${filename}`)
+      await setConfigOption('custom_background', `${localBgPath}/bg/${filename}`)
 
       // Copy the file over to the local directory
       await invoke('copy_file', {
         path: value.replace(/\\/g, '/'),
-        newPath: `${localBgPath}cultivation/bg/`,
+        newPath: `${localBgPath}/bg/`,
       })
 
       window.location.reload()
@@ -350,7 +349,7 @@ export default class Options extends React.Component<IProps, IState> {
 
   async installCert() {
     await invoke('generate_ca_files', {
-      path: (await dataDir()) + 'cultivation',
+      path: await getDataDir(),
     })
   }
 
@@ -1,7 +1,7 @@
-import { fs } from '@tauri-apps/api'
-import { dataDir } from '@tauri-apps/api/path'
+import { fs, invoke } from '@tauri-apps/api'
 
 let configFilePath: string
+let dataDirPath: string
 let defaultConfig: Configuration
 ;(async () => {
   defaultConfig = {
@@ -13,7 +13,7 @@ let defaultConfig: Configuration
     close_action: 0,
     startup_launch: false,
     last_ip: 'localhost',
-    last_port: '443',
+    last_port: 443,
     language: 'en',
     custom_background: '',
     use_theme_background: false,
@@ -35,10 +35,40 @@ let defaultConfig: Configuration
   }
 })()
 
+export interface ServerProfile {
+  host: string
+  port?: number | string
+  https?: boolean
+  redirect_more?: boolean
+  // Sent to the server as well as the game's own domains
+  redirect_domains?: string[]
+  patch_rsa?: boolean
+  launch_args?: string
+  // Custom public keys of the server, as <RSAKeyValue>
+  rsa_keys?: {
+    dispatch?: string
+    password?: string
+  }
+}
+
+export interface Hook {
+  command: string
+  args?: string[]
+  env?: Record<string, string>
+  working_dir?: string
+  // In seconds, 60 if not set
+  timeout?: number
+  // 'abort' calls the launch off when the hook fails, 'warn' only logs it
+  on_failure?: 'abort' | 'warn'
+  // Left running instead of waited for, only failing to start counts as failing
+  detach?: boolean
+}
+
 /**
  * 'close_action': 0 = close, 1 = tray
  */
 export interface Configuration {
+  config_version?: number
   toggle_grasscutter: boolean
   game_install_path: string
   grasscutter_with_game: boolean
@@ -47,7 +77,8 @@ export interface Configuration {
   close_action: number
   startup_launch: boolean
   last_ip: string
-  last_port: string
+  // Stored as a number since config_version 1, the backend still accepts text
+  last_port: number | string
   language: string
   custom_background: string
   use_theme_background: boolean
@@ -63,8 +94,23 @@ export interface Configuration {
   auto_mongodb: boolean
   un_elevated: boolean
   redirect_more: boolean
+  redirect_domains?: string[]
   launch_args: string
 
+  // Server profiles, see list_profiles/activate_profile
+  profiles?: Record<string, ServerProfile>
+  active_profile?: string
+
+  // Name -> executable of each registered game, see list_games/select_game
+  games?: Record<string, string>
+
+  // Run before launching, after launching and after the game closed, see run_hooks
+  hooks?: {
+    pre_launch?: Hook[]
+    post_launch?: Hook[]
+    post_exit?: Hook[]
+  }
+
   // Linux stuff
   grasscutter_elevation: string
 
@@ -80,10 +126,11 @@ export interface Configuration {
 }
 
 export async function setConfigOption<K extends keyof Configuration>(key: K, value: Configuration[K]): Promise<void> {
-  const config = await getConfig()
-  config[key] = value
+  // Ensures the config file exists before merging into it
+  await readConfigFile()
 
-  await saveConfig(<Configuration>config)
+  // Rejects with { code, message } if the value is invalid or the file can't be parsed
+  await invoke('update_config', { changes: { [key]: value } })
 }
 
 export async function getConfigOption<K extends keyof Configuration>(key: K): Promise<Configuration[K]> {
@@ -94,11 +141,12 @@ export async function getConfigOption<K extends keyof Configuration>(key: K): Pr
 }
 
 export async function getConfig() {
-  const raw = await readConfigFile()
+  await readConfigFile()
   let parsed: Configuration = defaultConfig
 
   try {
-    parsed = <Configuration>JSON.parse(raw)
+    // What the backend uses, including --set, --profile and CULTIVATION_* overrides
+    parsed = await invoke('load_config')
   } catch (e) {
     // We could not open the file
     console.log(e)
@@ -110,33 +158,38 @@ export async function getConfig() {
 }
 
 export async function saveConfig(obj: Configuration) {
-  const raw = JSON.stringify(obj)
+  await readConfigFile()
 
-  await writeConfigFile(raw)
+  await invoke('set_config', { config: obj })
+}
+
+/**
+ * Cultivation's data folder, without a trailing slash.
+ * Usually <data dir>/cultivation, but portable mode and --data-dir can put it anywhere.
+ */
+export async function getDataDir() {
+  if (!dataDirPath) dataDirPath = await invoke('get_data_dir')
+
+  return dataDirPath
 }
 
 async function readConfigFile() {
-  const local = await dataDir()
+  const local = await getDataDir()
 
-  if (!configFilePath) configFilePath = local + 'cultivation/configuration.json'
+  if (!configFilePath) configFilePath = local + '/configuration.json'
 
   // Ensure Cultivation dir exists
-  const dirs = await fs.readDir(local)
+  await fs.createDir(local, { recursive: true }).catch((e) => console.log(e))
 
-  if (!dirs.find((fileOrDir) => fileOrDir?.name === 'cultivation')) {
-    // Create dir
-    await fs.createDir(local + 'cultivation').catch((e) => console.log(e))
-  }
-
-  const innerDirs = await fs.readDir(local + 'cultivation')
+  const innerDirs = await fs.readDir(local)
 
   // Create grasscutter dir for potential installation
   if (!innerDirs.find((fileOrDir) => fileOrDir?.name === 'grasscutter')) {
     // Create dir
-    await fs.createDir(local + 'cultivation/grasscutter').catch((e) => console.log(e))
+    await fs.createDir(local + '/grasscutter').catch((e) => console.log(e))
   }
 
-  const dataFiles = await fs.readDir(local + 'cultivation')
+  const dataFiles = await fs.readDir(local)
 
   // Ensure config exists
   if (!dataFiles.find((fileOrDir) => fileOrDir?.name === 'configuration.json')) {
@@ -152,11 +205,3 @@ async function readConfigFile() {
   // Finally, read the file
   return await fs.readTextFile(configFilePath)
 }
-
-async function writeConfigFile(raw: string) {
-  // All external config functions call readConfigFile, which ensure files exists
-  await fs.writeFile({
-    path: configFilePath,
-    contents: raw,
-  })
-}
@@ -48,25 +48,45 @@ export async function getGameDataFolder() {
   return (await getGameFolder()) + '/' + gameExec.replace('.exe', '_Data')
 }
 
-export async function getGameVersion() {
-  const GameData = await getGameDataFolder()
+export interface GameVersion {
+  major: number
+  minor: number
+  release: number
+}
 
-  if (!GameData) {
+// Resolves to null if the version can't be determined, the reason is logged
+export async function getGameVersion() {
+  return invoke<GameVersion>('get_game_version').catch((e) => {
+    console.error(e)
     return null
-  }
+  })
+}
 
-  const settings = JSON.parse(
-    await invoke('read_file', {
-      path: GameData + '/StreamingAssets/asb_settings.json',
-    })
-  )
-
-  const versionRaw = settings.variance.split('.')
-  const version = {
-    major: parseInt(versionRaw[0]),
-    minor: parseInt(versionRaw[1].split('_')[0]),
-    release: parseInt(versionRaw[1].split('_')[1]),
+export type GameId = 'genshin' | 'star_rail' | 'honkai_3rd' | 'zenless_zone_zero'
+
+export interface GameEntry {
+  // What it was registered as
+  name: string
+  game: {
+    id: GameId
+    name: string
+    executables: { name: string; registry_key: string; login_value: string | null }[]
+    patch_manifest: string | null
+    redirect_domains: string[]
   }
+  path: string
+  active: boolean
+}
+
+export async function listGames() {
+  return invoke<GameEntry[]>('list_games')
+}
+
+// Remembers an install of a game as `name`, then selectGame switches game_install_path to it
+export async function registerGame(name: string, path: string) {
+  return invoke('register_game', { name, path })
+}
 
-  return version
+export async function selectGame(name: string) {
+  return invoke('select_game', { name })
 }
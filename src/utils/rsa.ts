@@ -1,10 +1,76 @@
 import { invoke } from '@tauri-apps/api'
+import { confirm } from '@tauri-apps/api/dialog'
 // Patch file from: https://github.com/34736384/RSAPatch/
 
-export async function patchGame() {
-  return invoke('patch_game')
+// Both resolve to true on success, and log the error code and message otherwise
+export async function patchGame(swapForeign = false): Promise<boolean> {
+  return invoke('patch_game', { swapForeign })
+    .then(() => true)
+    .catch(async (e) => {
+      // Another launcher's patch is in place, ask before putting it aside
+      if (e?.code === 'foreign_patch' && !swapForeign && (await confirm(e.message + '\n\nSwap it out for now?'))) {
+        return patchGame(true)
+      }
+
+      console.error(e)
+      return false
+    })
 }
 
 export async function unpatchGame() {
   return invoke('unpatch_game')
+    .then(() => true)
+    .catch((e) => {
+      console.error(e)
+      return false
+    })
+}
+
+export interface PatchAction {
+  op: 'rename' | 'copy' | 'delete' | 'keep'
+  from?: string
+  to?: string
+  path?: string
+}
+
+// What patchGame/unpatchGame would change in the game directory, without changing anything
+export async function planPatchGame() {
+  return invoke<PatchAction[]>('patch_game', { dryRun: true })
+}
+
+export async function planUnpatchGame() {
+  return invoke<PatchAction[]>('unpatch_game', { dryRun: true })
+}
+
+// Puts back the original game files from the patch journal, resolving to what was restored
+export async function repairPatch() {
+  return invoke<string[]>('repair_patch')
+}
+
+export interface PatchStatusReport {
+  status: 'clean' | 'patched_by_us' | 'patched_manually' | 'inconsistent'
+  files: {
+    path: string
+    sha256: string | null
+    known: 'original' | 'patch' | null
+  }[]
+  problems: string[]
+}
+
+export async function getPatchStatus() {
+  return invoke<PatchStatusReport>('patch_status')
+}
+
+export type KeyRole = 'dispatch' | 'password'
+
+export interface KeyInfo {
+  bits: number
+  exponent: string
+  xml: string
+  pem: string
+}
+
+// Stores a server's public key (<RSAKeyValue> or PEM) with a profile, to be used when patching
+export async function importRsaKey(profile: string, role: KeyRole, key: string) {
+  return invoke<KeyInfo>('import_rsa_key', { profile, role, key })
 }
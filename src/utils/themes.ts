@@ -1,7 +1,6 @@
 import { invoke } from '@tauri-apps/api'
-import { dataDir } from '@tauri-apps/api/path'
 import { convertFileSrc } from '@tauri-apps/api/tauri'
-import { getConfig, setConfigOption } from './configuration'
+import { getConfig, getDataDir, setConfigOption } from './configuration'
 
 interface Theme {
   name: string
@@ -40,7 +39,7 @@ const defaultTheme = {
 export async function getThemeList() {
   // Do some invoke to backend to get the theme list
   const themes = (await invoke('get_theme_list', {
-    dataDir: `${await dataDir()}cultivation`,
+    dataDir: await getDataDir(),
   })) as BackendThemeList[]
   const list: ThemeList[] = [
     // ALWAYS include default theme
@@ -123,7 +122,7 @@ export async function loadTheme(theme: ThemeList, document: Document) {
 
   // Set custom background
   if (theme.customBackgroundPath) {
-    const bgPath = (await dataDir()).replace(/\\/g, '/') + 'cultivation/bg/'
+    const bgPath = (await getDataDir()).replace(/\\/g, '/') + '/bg/'
     const imageName = theme.customBackgroundPath.split('/').pop()
 
     // Save the background to our data dir